@@ -0,0 +1,33 @@
+use futures_util::StreamExt;
+use tokio::signal::unix::{signal, SignalKind};
+use zbus::{Connection, Result};
+
+use crate::ctl::GlanceProxy;
+
+/// Prints the Waybar output of the running daemon and every update of it.
+/// Any number of bars can follow the same daemon, e.g. one per monitor
+pub async fn run() -> Result<()> {
+    // `pkill -SIGHUP glance` and `pkill -SIGRTMIN+2 glance` are meant for the daemon. Left unhandled,
+    // they would terminate every bar as well
+    let sigrtmin = libc::SIGRTMIN();
+    let _signals = [sigrtmin, sigrtmin + 2, sigrtmin + 3, sigrtmin + 4, sigrtmin + 5, sigrtmin + 6]
+        .into_iter()
+        .map(SignalKind::from_raw)
+        .chain([SignalKind::hangup()])
+        .map(signal)
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let connection = Connection::session().await?;
+    let glance = GlanceProxy::new(&connection).await?;
+    // Subscribes before asking for the current output, so no update falls in between
+    let mut updates = glance.receive_output_changed().await?;
+    // The daemon may not be running yet, its first output arrives as an update then
+    if let Ok(output) = glance.get_output().await
+        && !output.is_empty()
+    {
+        println!("{output}");
+    }
+    while let Some(update) = updates.next().await {
+        println!("{}", update.args()?.output);
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use clap::{Args, ColorChoice, FromArgMatches};
+
+use crate::{NotificationConfig, NotificationServer};
+
+/// `$XDG_CONFIG_HOME/glance/config.toml`, `$XDG_CONFIG_HOME` defaults to `~/.config`
+fn config_file() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("glance").join("config.toml"))
+}
+
+/// When the config file was last modified, `None` if there's none
+pub fn modified() -> Option<SystemTime> {
+    config_file()?.metadata().and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Options of the daemon given on the command line
+pub fn daemon_args() -> Vec<OsString> {
+    let mut args: Vec<_> = std::env::args_os().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "daemon") {
+        args.remove(0);
+    }
+    args
+}
+
+/// The config file merged with the options given on the command line, which take precedence.
+/// Keys are the long options without the dashes, e.g. `bar_format` or `bar-format` for `--bar-format`
+pub fn load(cli_args: &[OsString]) -> Result<NotificationConfig, String> {
+    let Some(path) = config_file().filter(|path| path.exists()) else {
+        return parse(Vec::new(), cli_args);
+    };
+    let error = |err: &dyn std::fmt::Display| format!("{}: {err}", path.display());
+    let contents = std::fs::read_to_string(&path).map_err(|err| error(&err))?;
+    let table = contents.parse::<toml::Table>().map_err(|err| error(&err.message()))?;
+    let file_args = to_args(&table).map_err(|err| error(&err))?;
+    parse(file_args, cli_args).map_err(|err| error(&err))
+}
+
+fn parse(file_args: Vec<OsString>, cli_args: &[OsString]) -> Result<NotificationConfig, String> {
+    // Later occurrences of an option override earlier ones, so the command line wins over the file
+    let command = NotificationConfig::augment_args(clap::Command::new("glance"))
+        .no_binary_name(true)
+        .args_override_self(true)
+        .color(ColorChoice::Never);
+    command
+        .try_get_matches_from(file_args.into_iter().chain(cli_args.iter().cloned()))
+        .and_then(|matches| NotificationConfig::from_arg_matches(&matches))
+        .map_err(|err| {
+            let message = err.to_string();
+            let message = message.lines().next().unwrap_or_default();
+            message.strip_prefix("error: ").unwrap_or(message).to_string()
+        })
+}
+
+fn to_args(table: &toml::Table) -> Result<Vec<OsString>, String> {
+    let mut args = Vec::new();
+    for (key, value) in table {
+        let option = format!("--{}", key.replace('_', "-"));
+        match value {
+            toml::Value::Boolean(true) => args.push(option.into()),
+            toml::Value::Boolean(false) => {}
+            toml::Value::String(text) => args.push(format!("{option}={text}").into()),
+            toml::Value::Integer(number) => args.push(format!("{option}={number}").into()),
+            toml::Value::Float(number) => args.push(format!("{option}={number}").into()),
+            _ => return Err(format!("{key} has to be a string, a number or a boolean")),
+        }
+    }
+    Ok(args)
+}
+
+impl NotificationServer {
+    /// Applies the config file again, history stays. On error the previous config is kept
+    pub(crate) fn reload_config(&mut self, cli_args: &[OsString]) {
+        match load(cli_args) {
+            Ok(config) => {
+                let previous = std::mem::replace(&mut self.config, config);
+                // Decided once the daemon starts, changing them takes a restart
+                self.config.monitor = previous.monitor;
+                self.config.forward_to = previous.forward_to;
+                self.config.forward_min_urgency = previous.forward_min_urgency;
+                self.config.replace = previous.replace;
+                // History is only loaded at takeover, saving it now would overwrite the file with what's in memory
+                self.config.persist = previous.persist;
+                self.config_error = None;
+                self.set_marquee(None);
+            }
+            Err(err) => {
+                eprintln!("Failed to reload config: {err}");
+                self.config_error = Some(err);
+            }
+        }
+        self.display_notifications_on_bar();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_from(file: &str, cli: &[&str]) -> Result<NotificationConfig, String> {
+        let table = file.parse::<toml::Table>().map_err(|err| err.message().to_string())?;
+        let cli_args: Vec<OsString> = cli.iter().map(OsString::from).collect();
+        parse(to_args(&table)?, &cli_args)
+    }
+
+    #[test]
+    fn command_line_overrides_file() {
+        let config = load_from("bar_max_width = 40\nellipsis = \"...\"", &["--bar-max-width", "20"]).unwrap();
+        assert_eq!(config.bar_max_width, 20);
+        assert_eq!(config.ellipsis, "...");
+    }
+
+    #[test]
+    fn keys_may_use_either_case() {
+        let config = load_from("bar_max_width = 40\nbar-line-separator = \" | \"", &[]).unwrap();
+        assert_eq!(config.bar_max_width, 40);
+        assert_eq!(config.bar_line_separator, " | ");
+    }
+
+    #[test]
+    fn booleans_are_flags() {
+        assert!(load_from("persist = true", &[]).unwrap().persist);
+        assert!(!load_from("persist = false", &[]).unwrap().persist);
+        assert_eq!(to_args(&"persist = false".parse().unwrap()).unwrap(), Vec::<OsString>::new());
+    }
+
+    #[test]
+    fn non_scalar_values_are_rejected() {
+        assert_eq!(
+            load_from("opener = [\"xdg-open\"]", &[]).unwrap_err(),
+            "opener has to be a string, a number or a boolean"
+        );
+        assert!(load_from("[bar]\nmax_width = 1", &[]).is_err());
+    }
+
+    #[test]
+    fn unknown_keys_are_reported() {
+        assert_eq!(load_from("colour = \"red\"", &[]).unwrap_err(), "unexpected argument '--colour' found");
+    }
+
+    #[test]
+    fn invalid_values_are_reported() {
+        let err = load_from("bar_max_width = \"wide\"", &[]).unwrap_err();
+        assert!(err.starts_with("invalid value 'wide' for '--bar-max-width"), "{err}");
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use zbus::{interface, object_server::SignalEmitter, zvariant::Value, ObjectServer};
+
+use crate::{NotificationServer, OBJECT_PATH};
+
+/// Glance specific interface for controlling the daemon, e.g. from Waybar click handlers.
+/// Lives next to `org.freedesktop.Notifications` at the same object path
+/// and is reachable under the `io.github.piwonskp.Glance` name as well.
+pub struct Control;
+
+#[interface(name = "io.github.piwonskp.Glance1")]
+impl Control {
+    /// Marks the visible notification as read and shows the next one
+    async fn next(&self, #[zbus(object_server)] server: &ObjectServer) -> zbus::fdo::Result<()> {
+        let server = server.interface::<_, NotificationServer>(OBJECT_PATH).await?;
+        server.get_mut().await.next_notification();
+        Ok(())
+    }
+
+    /// Marks the visible notification as read and shows the previous one
+    async fn previous(&self, #[zbus(object_server)] server: &ObjectServer) -> zbus::fdo::Result<()> {
+        let server = server.interface::<_, NotificationServer>(OBJECT_PATH).await?;
+        server.get_mut().await.previous_notification();
+        Ok(())
+    }
+
+    /// Marks the visible notification as read
+    async fn mark_read(&self, #[zbus(object_server)] server: &ObjectServer) -> zbus::fdo::Result<()> {
+        let server = server.interface::<_, NotificationServer>(OBJECT_PATH).await?;
+        server.get_mut().await.mark_read_and_render();
+        Ok(())
+    }
+
+    /// Marks every notification in history as read
+    async fn mark_all_read(&self, #[zbus(object_server)] server: &ObjectServer) -> zbus::fdo::Result<()> {
+        let server = server.interface::<_, NotificationServer>(OBJECT_PATH).await?;
+        server.get_mut().await.mark_all_read_and_render();
+        Ok(())
+    }
+
+    /// Closes the visible notification as dismissed by the user
+    async fn close(&self, #[zbus(object_server)] server: &ObjectServer) -> zbus::fdo::Result<()> {
+        let server = server.interface::<_, NotificationServer>(OBJECT_PATH).await?;
+        server.get_mut().await.dismiss(server.signal_emitter()).await?;
+        Ok(())
+    }
+
+    /// Removes all notifications from history
+    async fn clear_all(&self, #[zbus(object_server)] server: &ObjectServer) -> zbus::fdo::Result<()> {
+        let server = server.interface::<_, NotificationServer>(OBJECT_PATH).await?;
+        server.get_mut().await.clear_all(server.signal_emitter()).await?;
+        Ok(())
+    }
+
+    /// Invokes an action of the visible notification. Empty key picks the default action,
+    /// empty token skips the `ActivationToken` signal
+    async fn invoke_action(
+        &self,
+        #[zbus(object_server)] server: &ObjectServer,
+        action_key: &str,
+        activation_token: &str,
+    ) -> zbus::fdo::Result<()> {
+        let server = server.interface::<_, NotificationServer>(OBJECT_PATH).await?;
+        let action_key = Some(action_key).filter(|key| !key.is_empty());
+        let activation_token = Some(activation_token).filter(|token| !token.is_empty());
+        server
+            .get_mut()
+            .await
+            .invoke_action(server.signal_emitter(), action_key, activation_token)
+            .await?;
+        Ok(())
+    }
+
+    /// Opens a link from the body of the visible notification, counting from 0
+    async fn open_link(&self, #[zbus(object_server)] server: &ObjectServer, index: u32) -> zbus::fdo::Result<()> {
+        let server = server.interface::<_, NotificationServer>(OBJECT_PATH).await?;
+        server
+            .get()
+            .await
+            .open_link(index as usize)
+            .map_err(|err| zbus::fdo::Error::Failed(format!("Failed to open link: {err}")))
+    }
+
+    /// Enables or disables Do Not Disturb
+    async fn set_dnd(&self, #[zbus(object_server)] server: &ObjectServer, dnd: bool) -> zbus::fdo::Result<()> {
+        let server = server.interface::<_, NotificationServer>(OBJECT_PATH).await?;
+        server.get_mut().await.set_dnd(dnd);
+        Ok(())
+    }
+
+    /// Id of the visible notification (0 if none), unread and total notification count
+    /// and whether Do Not Disturb is enabled
+    async fn get_state(
+        &self,
+        #[zbus(object_server)] server: &ObjectServer,
+    ) -> zbus::fdo::Result<HashMap<&str, Value<'_>>> {
+        let server = server.interface::<_, NotificationServer>(OBJECT_PATH).await?;
+        let server = server.get().await;
+        Ok(HashMap::from([
+            ("visible", Value::from(server.visible_id().unwrap_or(0))),
+            ("unread", Value::from(server.unread_count() as u32)),
+            ("total", Value::from(server.history.len() as u32)),
+            ("dnd", Value::from(server.dnd)),
+        ]))
+    }
+
+    /// The last Waybar output as JSON, empty before the daemon rendered anything
+    async fn get_output(&self, #[zbus(object_server)] server: &ObjectServer) -> zbus::fdo::Result<String> {
+        let server = server.interface::<_, NotificationServer>(OBJECT_PATH).await?;
+        let server = server.get().await;
+        Ok(match &server.last_output {
+            serde_json::Value::Null => String::new(),
+            output => output.to_string(),
+        })
+    }
+
+    /// Emitted with every new Waybar output, `glance bar` prints it
+    #[zbus(signal)]
+    pub async fn output_changed(emitter: &SignalEmitter<'_>, output: &str) -> zbus::Result<()>;
+}
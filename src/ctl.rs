@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use clap::{Subcommand, ValueEnum};
+use serde_json::json;
+use zbus::{proxy, zvariant::{OwnedValue, Value}, Connection, Result};
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum CtlCommand {
+    /// Mark the visible notification as read and show the next one
+    Next,
+    /// Mark the visible notification as read and show the previous one
+    Prev,
+    /// Mark the visible notification as read
+    Read,
+    /// Mark all notifications as read
+    ReadAll,
+    /// Close the visible notification
+    Close,
+    /// Remove all notifications
+    Clear,
+    /// Invoke an action of the visible notification, the default one unless a key is given
+    Action { key: Option<String> },
+    /// Open a link from the visible notification with the configured opener
+    Open {
+        /// Which link to open, counting from 1
+        #[arg(default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        index: u32,
+    },
+    /// Turn Do Not Disturb on or off
+    Dnd { mode: DndMode },
+    /// Print the state of the daemon as JSON
+    Status,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum DndMode {
+    On,
+    Off,
+    Toggle,
+}
+
+#[proxy(
+    interface = "io.github.piwonskp.Glance1",
+    default_service = "io.github.piwonskp.Glance",
+    default_path = "/org/freedesktop/Notifications",
+    gen_blocking = false
+)]
+pub(crate) trait Glance {
+    fn next(&self) -> Result<()>;
+    fn previous(&self) -> Result<()>;
+    fn mark_read(&self) -> Result<()>;
+    fn mark_all_read(&self) -> Result<()>;
+    fn close(&self) -> Result<()>;
+    fn clear_all(&self) -> Result<()>;
+    fn invoke_action(&self, action_key: &str, activation_token: &str) -> Result<()>;
+    fn open_link(&self, index: u32) -> Result<()>;
+    fn set_dnd(&self, dnd: bool) -> Result<()>;
+    fn get_state(&self) -> Result<HashMap<String, OwnedValue>>;
+    fn get_output(&self) -> Result<String>;
+
+    #[zbus(signal)]
+    fn output_changed(&self, output: String) -> Result<()>;
+}
+
+pub async fn run(command: CtlCommand) -> Result<()> {
+    let connection = Connection::session().await?;
+    let glance = GlanceProxy::new(&connection).await?;
+    match command {
+        CtlCommand::Next => glance.next().await,
+        CtlCommand::Prev => glance.previous().await,
+        CtlCommand::Read => glance.mark_read().await,
+        CtlCommand::ReadAll => glance.mark_all_read().await,
+        CtlCommand::Close => glance.close().await,
+        CtlCommand::Clear => glance.clear_all().await,
+        CtlCommand::Action { key } => {
+            // Lets the application raise its window when the click comes from a launcher that provides a token
+            let activation_token = std::env::var("XDG_ACTIVATION_TOKEN").unwrap_or_default();
+            glance
+                .invoke_action(key.as_deref().unwrap_or_default(), &activation_token)
+                .await
+        }
+        CtlCommand::Open { index } => glance.open_link(index - 1).await,
+        CtlCommand::Dnd { mode } => {
+            let dnd = match mode {
+                DndMode::On => true,
+                DndMode::Off => false,
+                DndMode::Toggle => {
+                    let state = glance.get_state().await?;
+                    !state.get("dnd").and_then(|dnd| bool::try_from(dnd).ok()).unwrap_or(false)
+                }
+            };
+            glance.set_dnd(dnd).await
+        }
+        CtlCommand::Status => {
+            let state = glance
+                .get_state()
+                .await?
+                .into_iter()
+                .map(|(key, value)| (key, to_json(&value)))
+                .collect::<serde_json::Map<_, _>>();
+            println!("{}", serde_json::Value::Object(state));
+            Ok(())
+        }
+    }
+}
+
+fn to_json(value: &OwnedValue) -> serde_json::Value {
+    match &**value {
+        Value::U32(number) => json!(number),
+        Value::Bool(flag) => json!(flag),
+        value => json!(value.to_string()),
+    }
+}
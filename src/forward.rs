@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use futures_util::StreamExt;
+use zbus::{proxy, zvariant::OwnedValue, Connection, Result};
+
+use crate::{CloseReason, Notification, NotificationServer, Urgency, OBJECT_PATH};
+
+#[proxy(interface = "org.freedesktop.Notifications", default_path = "/org/freedesktop/Notifications", gen_blocking = false)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[String],
+        hints: &HashMap<String, OwnedValue>,
+        expire_timeout: i32,
+    ) -> Result<u32>;
+    fn close_notification(&self, id: u32) -> Result<()>;
+
+    #[zbus(signal)]
+    fn notification_closed(&self, id: u32, reason: u32) -> Result<()>;
+    #[zbus(signal)]
+    fn action_invoked(&self, id: u32, action_key: String) -> Result<()>;
+    #[zbus(signal)]
+    fn activation_token(&self, id: u32, activation_token: String) -> Result<()>;
+}
+
+/// Passes notifications on to a popup daemon owning another bus name
+pub struct Forward {
+    proxy: NotificationsProxy<'static>,
+    min_urgency: Urgency,
+    /// Ids of the notifications in the downstream daemon by their ids in Glance
+    ids: HashMap<u32, u32>,
+}
+
+impl Forward {
+    pub async fn new(connection: &Connection, destination: String, min_urgency: Urgency) -> Result<Self> {
+        let proxy = NotificationsProxy::builder(connection).destination(destination)?.build().await?;
+        Ok(Self { proxy, min_urgency, ids: HashMap::new() })
+    }
+
+    /// Id in Glance of a notification in the downstream daemon
+    fn glance_id(&self, downstream_id: u32) -> Option<u32> {
+        self.ids
+            .iter()
+            .find(|(_, downstream)| **downstream == downstream_id)
+            .map(|(id, _)| *id)
+    }
+}
+
+/// Arguments of a `Notify` call as received from the application
+pub struct Notify {
+    pub app_name: String,
+    pub app_icon: String,
+    pub summary: String,
+    pub body: String,
+    pub actions: Vec<String>,
+    pub hints: HashMap<String, OwnedValue>,
+    pub expire_timeout: i32,
+}
+
+impl NotificationServer {
+    /// Shows the notification with the given id in the downstream daemon as well, if its urgency is high enough
+    /// and Do Not Disturb doesn't silence it
+    pub(crate) fn forward_notify(&mut self, connection: &Connection, id: u32, notify: Notify) {
+        let Some(forward) = &self.forward else {
+            return;
+        };
+        let skipped = |notification: &Notification| {
+            notification.urgency < forward.min_urgency || self.is_silenced(notification)
+        };
+        if self.history.get(&id).is_none_or(skipped) {
+            return;
+        }
+        let proxy = forward.proxy.clone();
+        let replaces_id = forward.ids.get(&id).copied().unwrap_or(0);
+        let connection = connection.clone();
+        tokio::spawn(async move {
+            let result = proxy
+                .notify(
+                    &notify.app_name,
+                    replaces_id,
+                    &notify.app_icon,
+                    &notify.summary,
+                    &notify.body,
+                    &notify.actions,
+                    &notify.hints,
+                    notify.expire_timeout,
+                )
+                .await;
+            let downstream_id = match result {
+                Ok(downstream_id) => downstream_id,
+                Err(err) => {
+                    eprintln!("Failed to forward notification: {err}");
+                    return;
+                }
+            };
+            let Ok(server) = connection.object_server().interface::<_, NotificationServer>(OBJECT_PATH).await else {
+                return;
+            };
+            let mut server = server.get_mut().await;
+            let Some(forward) = &mut server.forward else {
+                return;
+            };
+            forward.ids.insert(id, downstream_id);
+            // Closed in Glance while the downstream daemon was still showing it
+            if !server.history.contains_key(&id) {
+                server.forget_forwarded(id);
+            }
+        });
+    }
+
+    /// Closes the downstream copy of a notification that's gone from Glance
+    pub(crate) fn forget_forwarded(&mut self, id: u32) {
+        let Some(forward) = &mut self.forward else {
+            return;
+        };
+        if let Some(downstream_id) = forward.ids.remove(&id) {
+            let proxy = forward.proxy.clone();
+            tokio::spawn(async move {
+                if let Err(err) = proxy.close_notification(downstream_id).await {
+                    eprintln!("Failed to close forwarded notification: {err}");
+                }
+            });
+        }
+    }
+}
+
+/// Relays `ActionInvoked`, `ActivationToken` and `NotificationClosed` signals of the downstream daemon
+/// to the applications, with the ids translated to the ones Glance gave out
+pub async fn relay(connection: Connection) -> Result<()> {
+    let server = connection.object_server().interface::<_, NotificationServer>(OBJECT_PATH).await?;
+    let Some(proxy) = server.get().await.forward.as_ref().map(|forward| forward.proxy.clone()) else {
+        return Ok(());
+    };
+    let mut closed = proxy.receive_notification_closed().await?;
+    let mut actions = proxy.receive_action_invoked().await?;
+    let mut tokens = proxy.receive_activation_token().await?;
+    let emitter = server.signal_emitter();
+
+    // A malformed signal or a failed emit only affects that one notification, relaying goes on
+    loop {
+        tokio::select! {
+            Some(signal) = closed.next() => {
+                let args = match signal.args() {
+                    Ok(args) => args,
+                    Err(err) => {
+                        eprintln!("Failed to parse NotificationClosed of the popup daemon: {err}");
+                        continue;
+                    }
+                };
+                let mut server = server.get_mut().await;
+                let Some(id) = server.forward.as_mut().and_then(|forward| forward.glance_id(args.id)) else {
+                    continue;
+                };
+                if let Some(forward) = &mut server.forward {
+                    forward.ids.remove(&id);
+                }
+                // A popup that timed out says nothing about the notification, Glance expires it on its own.
+                // Popups closed after an action report a dismissal as well
+                if args.reason != CloseReason::Dismissed as u32 {
+                    continue;
+                }
+                let Some(notification) = server.history.get_mut(&id) else {
+                    continue;
+                };
+                if notification.closed {
+                    continue;
+                }
+                notification.closed = true;
+                // The user has seen it in the popup
+                notification.read = true;
+                if let Err(err) = server.emit_closed(emitter, id, CloseReason::Dismissed).await {
+                    eprintln!("Failed to relay NotificationClosed: {err}");
+                }
+                server.save();
+                server.display_notifications_on_bar();
+            },
+            Some(signal) = actions.next() => {
+                let args = match signal.args() {
+                    Ok(args) => args,
+                    Err(err) => {
+                        eprintln!("Failed to parse ActionInvoked of the popup daemon: {err}");
+                        continue;
+                    }
+                };
+                let mut server = server.get_mut().await;
+                let Some(id) = server.forward.as_ref().and_then(|forward| forward.glance_id(args.id)) else {
+                    continue;
+                };
+                let sender_emitter = server.client_emitter(emitter, id);
+                if let Err(err) = NotificationServer::action_invoked(&sender_emitter, id, &args.action_key).await {
+                    eprintln!("Failed to relay ActionInvoked: {err}");
+                }
+                if let Some(index) = server.history.get_index_of(&id) {
+                    server.mark_read(index);
+                    server.save();
+                    server.display_notifications_on_bar();
+                }
+            },
+            Some(signal) = tokens.next() => {
+                let args = match signal.args() {
+                    Ok(args) => args,
+                    Err(err) => {
+                        eprintln!("Failed to parse ActivationToken of the popup daemon: {err}");
+                        continue;
+                    }
+                };
+                let server = server.get().await;
+                let Some(id) = server.forward.as_ref().and_then(|forward| forward.glance_id(args.id)) else {
+                    continue;
+                };
+                let sender_emitter = server.client_emitter(emitter, id);
+                if let Err(err) = NotificationServer::activation_token(&sender_emitter, id, &args.activation_token).await {
+                    eprintln!("Failed to relay ActivationToken: {err}");
+                }
+            },
+            else => return Ok(()),
+        }
+    }
+}
@@ -1,41 +1,382 @@
 use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
 
 use tokio::signal::unix::{signal, SignalKind};
-use zbus::{zvariant::Value, Connection, Result};
+use tokio::sync::watch;
+use tokio::task::AbortHandle;
+use zbus::{
+    fdo::{DBusProxy, RequestNameFlags, RequestNameReply},
+    message::Header,
+    names::OwnedUniqueName,
+    object_server::SignalEmitter,
+    zvariant::Value,
+    Connection, Result,
+};
+use futures_util::StreamExt;
 use serde_json::json;
 use std::io::Write;
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use chrono::{format::{Item, StrftimeItems}, DateTime, Local};
 
+use control::Control;
+use ctl::CtlCommand;
+use forward::Forward;
+use markup::MarkupMode;
+use template::Template;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Parser)]
+mod bar;
+mod config;
+mod control;
+mod ctl;
+mod forward;
+mod markup;
+mod monitor;
+mod state;
+mod template;
+
+const OBJECT_PATH: &str = "/org/freedesktop/Notifications";
+const NOTIFICATIONS_NAME: &str = "org.freedesktop.Notifications";
+const CONTROL_NAME: &str = "io.github.piwonskp.Glance";
+/// How often the config file is checked for changes
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// Separates the end of scrolling text from its beginning
+const MARQUEE_GAP: &str = "   ";
+
+#[derive(Debug, Parser)]
 #[command(author = "Piotr Piwoński <piwonskp@gmail.com>", version = env!("CARGO_PKG_VERSION"), about = "A notification server for waybar")]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Running without a subcommand starts the daemon
+    #[command(flatten)]
+    config: NotificationConfig,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the notification daemon, the default when no subcommand is given
+    Daemon(Box<NotificationConfig>),
+    /// Print the output of the running daemon for Waybar, e.g. on each monitor
+    Bar,
+    /// Control the running daemon
+    Ctl {
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Args)]
 struct NotificationConfig {
-    #[arg(long, default_value = "<b>•</b> [{app}] <b>{summary}</b>: {body}")]
-    read_format: String,
+    /// Format of read notifications in the tooltip
+    #[arg(long, default_value = "<b>•</b> [{app}] <b>{summary}</b>{?body}: {body}{/}", value_parser = Template::parse)]
+    read_format: Template,
+
+    /// Format of unread notifications in the tooltip
+    #[arg(
+        long,
+        default_value = "<span color='#00d69e'><b>• [{app}] {summary}{?body}: {body}{/}</b></span>",
+        value_parser = Template::parse
+    )]
+    unread_format: Template,
+
+    /// Format of the notification shown on the bar
+    #[arg(long, default_value = "[{app}] <b>{summary}</b>{?body}: {body}{/}", value_parser = Template::parse)]
+    bar_format: Template,
+
+    /// Lines of the text on the bar are joined with this separator
+    #[arg(long, default_value = " · ")]
+    bar_line_separator: String,
+
+    /// Prefix of continuation lines of a notification in the tooltip
+    #[arg(long, default_value = "    ")]
+    tooltip_indent: String,
+
+    /// Maximum width of the text on the bar in display columns. 0 means unlimited
+    #[arg(long, default_value_t = 0)]
+    bar_max_width: usize,
+
+    /// Appended to text shortened by --bar-max-width or the truncate filter
+    #[arg(long, default_value = "…")]
+    ellipsis: String,
+
+    /// What happens to bar text longer than --bar-max-width
+    #[arg(long, value_enum, default_value_t = BarOverflow::Truncate)]
+    bar_overflow: BarOverflow,
+
+    /// How often in milliseconds scrolling text moves by one character
+    #[arg(long, default_value_t = 300)]
+    scroll_interval: u64,
+
+    /// Timeout in milliseconds for notifications that leave it up to the server. 0 means never
+    #[arg(long, default_value_t = 0)]
+    default_timeout: u32,
+
+    /// What happens to a notification once its timeout passes
+    #[arg(long, value_enum, default_value_t = ExpireAction::Remove)]
+    on_expire: ExpireAction,
+
+    /// Start with Do Not Disturb enabled
+    #[arg(long)]
+    dnd: bool,
+
+    /// Show critical notifications even when Do Not Disturb is enabled
+    #[arg(long)]
+    dnd_bypass_critical: bool,
+
+    /// Keep notification history in $XDG_STATE_HOME/glance across restarts
+    #[arg(long)]
+    persist: bool,
+
+    /// How often in seconds to re-render notifications with relative time like {age}. 0 disables it
+    #[arg(long, default_value_t = 60)]
+    refresh_interval: u64,
+
+    /// strftime-like format of the {time} placeholder
+    #[arg(long, default_value = "%H:%M", value_parser = parse_time_format)]
+    time_format: String,
+
+    /// strftime-like format of the {date} placeholder
+    #[arg(long, default_value = "%Y-%m-%d", value_parser = parse_time_format)]
+    date_format: String,
+
+    /// How markup in the application name is treated
+    #[arg(long, value_enum, default_value_t = MarkupMode::Escape)]
+    app_markup: MarkupMode,
+
+    /// How markup in the summary is treated
+    #[arg(long, value_enum, default_value_t = MarkupMode::Escape)]
+    summary_markup: MarkupMode,
+
+    /// How markup in the body is treated. On the bar sanitized markup is reduced to plain text
+    #[arg(long, value_enum, default_value_t = MarkupMode::Sanitize)]
+    body_markup: MarkupMode,
+
+    /// Don't receive notifications, mirror the ones shown by another notification daemon into history instead
+    #[arg(long, conflicts_with = "replace")]
+    monitor: bool,
+
+    /// Pass notifications on to a popup daemon owning this bus name, e.g. org.freedesktop.Notifications.Popups
+    #[arg(long, conflicts_with = "monitor", value_parser = parse_forward_to)]
+    forward_to: Option<String>,
+
+    /// Only notifications at least this urgent are passed on with --forward-to
+    #[arg(long, value_enum, default_value_t = Urgency::Low)]
+    forward_min_urgency: Urgency,
+
+    /// Take over the bus names from the running notification daemon instead of waiting for it to exit
+    #[arg(long)]
+    replace: bool,
+
+    /// Command that opens links from notifications, the link is passed as the last argument
+    #[arg(long, default_value = "xdg-open")]
+    opener: String,
+}
+
+fn parse_time_format(format: &str) -> std::result::Result<String, String> {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err(format!("invalid time format: {format}"));
+    }
+    Ok(format.to_string())
+}
+
+/// Forwarding to one of Glance's own names would send every notification back to itself
+fn parse_forward_to(destination: &str) -> std::result::Result<String, String> {
+    if [NOTIFICATIONS_NAME, CONTROL_NAME].contains(&destination) {
+        return Err(format!("{destination} is owned by Glance, pass the bus name of another daemon"));
+    }
+    Ok(destination.to_string())
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum ExpireAction {
+    /// Remove the notification from history
+    Remove,
+    /// Keep the notification in history, but take it off the bar and mark it read
+    MarkRead,
+}
 
-    #[arg(long, default_value = "<span color='#00d69e'><b>• [{app}] {summary}: {body}</b></span>")]
-    unread_format: String,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum BarOverflow {
+    /// Cut the text and append the ellipsis
+    Truncate,
+    /// Scroll the text of a new notification until it's read or the user navigates, then truncate it
+    Scroll,
+}
+
+/// Reasons passed along with the `NotificationClosed` signal, as defined by the spec
+#[derive(Debug, Clone, Copy)]
+enum CloseReason {
+    Expired = 1,
+    Dismissed = 2,
+    Closed = 3,
+}
 
-    #[arg(long, default_value = "[{app}] <b>{summary}</b>: {body}")]
-    bar_format: String,
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum Urgency {
+    Low,
+    #[default]
+    Normal,
+    Critical,
+}
+
+impl Urgency {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Urgency::Low => "low",
+            Urgency::Normal => "normal",
+            Urgency::Critical => "critical",
+        }
+    }
+}
+
+impl From<u8> for Urgency {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Urgency::Low,
+            2 => Urgency::Critical,
+            _ => Urgency::Normal,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Notification {
     app_name: String,
     summary: String,
     body: String,
+    urgency: Urgency,
     read: bool,
+    /// Action key and label pairs, in the order the client sent them
+    actions: Vec<(String, String)>,
+    /// Don't close the notification once an action is invoked
+    resident: bool,
+    /// Targets of the links in the body
+    #[serde(default)]
+    links: Vec<String>,
+    /// Unique bus name of the client, action signals are sent only there. Names are reused
+    /// after the bus restarts, so it isn't saved
+    #[serde(skip)]
+    sender: Option<OwnedUniqueName>,
+    /// The client was already told the notification is closed, it's only kept in history
+    closed: bool,
+    /// When the pending expiration fires, kept to re-arm the timer after a restart
+    #[serde(default)]
+    expires_at: Option<SystemTime>,
+    #[serde(default = "SystemTime::now")]
+    received_at: SystemTime,
 }
 
 impl Notification {
-    fn format_with(&self, format: &str) -> String {
-        format
-            .replace("{app}", &self.app_name)
-            .replace("{summary}", &self.summary)
-            .replace("{body}", &self.body)
+    /// Picks the requested action or the one with the "default" key
+    fn action(&self, key: Option<&str>) -> Option<&str> {
+        if self.closed {
+            return None;
+        }
+        let key = key.unwrap_or("default");
+        self.actions
+            .iter()
+            .find(|(action, _)| action == key)
+            .map(|(action, _)| action.as_str())
+    }
+
+    fn new(
+        app_name: &str,
+        summary: &str,
+        body: &str,
+        actions: Vec<String>,
+        hints: &HashMap<String, Value>,
+        sender: Option<OwnedUniqueName>,
+    ) -> Self {
+        Self {
+            app_name: app_name.to_string(),
+            summary: summary.to_string(),
+            body: body.to_string(),
+            urgency: hints
+                .get("urgency")
+                .and_then(|value| u8::try_from(value).ok())
+                .map(Urgency::from)
+                .unwrap_or_default(),
+            read: false,
+            actions: actions
+                .chunks_exact(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect(),
+            resident: hints
+                .get("resident")
+                .and_then(|value| bool::try_from(value).ok())
+                .unwrap_or(false),
+            links: markup::links(body),
+            sender,
+            closed: false,
+            expires_at: None,
+            received_at: SystemTime::now(),
+        }
+    }
+
+    /// How long ago the notification arrived, e.g. "5m ago"
+    fn age(&self) -> String {
+        let seconds = SystemTime::now()
+            .duration_since(self.received_at)
+            .unwrap_or_default()
+            .as_secs();
+        match seconds {
+            0..60 => "just now".to_string(),
+            60..3600 => format!("{}m ago", seconds / 60),
+            3600..86400 => format!("{}h ago", seconds / 3600),
+            _ => format!("{}d ago", seconds / 86400),
+        }
+    }
+}
+
+/// Links come from the sender of the notification, so local files, custom schemes
+/// and anything the opener could take for an option are refused
+fn is_safe_link(link: &str) -> bool {
+    let Some((scheme, _)) = link.split_once(':') else {
+        return false;
+    };
+    !link.starts_with('-') && ["http", "https", "mailto"].iter().any(|allowed| scheme.eq_ignore_ascii_case(allowed))
+}
+
+/// Values of the placeholders for rendering a single notification
+struct Placeholders<'a> {
+    notification: &'a Notification,
+    config: &'a NotificationConfig,
+    body_markup: MarkupMode,
+    unread: usize,
+    total: usize,
+    /// Position of the notification in history, counting from 1
+    position: usize,
+}
+
+impl template::Values for Placeholders<'_> {
+    fn markup(&self, name: &str, literal: bool) -> String {
+        let mode = |mode| if literal { MarkupMode::Escape } else { mode };
+        let notification = self.notification;
+        let received_at = DateTime::<Local>::from(notification.received_at);
+        match name {
+            "app" => mode(self.config.app_markup).apply(&notification.app_name).into_owned(),
+            "summary" => mode(self.config.summary_markup).apply(&notification.summary).into_owned(),
+            "body" => mode(self.body_markup).apply(&notification.body).into_owned(),
+            "urgency" => notification.urgency.as_str().to_string(),
+            "time" => markup::escape(&received_at.format(&self.config.time_format).to_string()).into_owned(),
+            "date" => markup::escape(&received_at.format(&self.config.date_format).to_string()).into_owned(),
+            "age" => notification.age(),
+            "unread" => self.unread.to_string(),
+            "total" => self.total.to_string(),
+            "position" => format!("{}/{}", self.position, self.total),
+            _ => String::new(),
+        }
+    }
+
+    fn ellipsis(&self) -> &str {
+        &self.config.ellipsis
     }
 }
 
@@ -44,61 +385,393 @@ struct NotificationServer {
     visible_on_bar: Option<usize>,
     last_notification_id: u32,
     config: NotificationConfig,
+    /// Pending expiration timers by notification id
+    timers: HashMap<u32, AbortHandle>,
+    /// Do Not Disturb, new notifications don't show up on the bar
+    dnd: bool,
+    /// Last output printed for Waybar
+    last_output: serde_json::Value,
+    /// Id of the notification scrolling on the bar and how far it scrolled
+    marquee: Option<(u32, usize)>,
+    /// Whether anything scrolls, the scroll timer only ticks meanwhile
+    scrolling: watch::Sender<bool>,
+    /// Every output is passed on to `glance bar` clients as well
+    outputs: watch::Sender<String>,
+    /// Serialized state waiting to be written to disk
+    snapshots: watch::Sender<Vec<u8>>,
+    /// Owns the notifications bus name. Otherwise another daemon receives notifications
+    active: bool,
+    /// Popup daemon that gets a copy of notifications
+    forward: Option<Forward>,
+    /// Why the config file couldn't be applied, shown on the bar until it's fixed
+    config_error: Option<String>,
 }
 
 impl NotificationServer {
-    fn new() -> Self {
-        let config = NotificationConfig::parse();
+    fn new(config: NotificationConfig) -> Self {
         Self {
             history: IndexMap::new(),
             visible_on_bar: None,
             last_notification_id: 0,
+            dnd: config.dnd,
             config,
+            timers: HashMap::new(),
+            last_output: serde_json::Value::Null,
+            marquee: None,
+            scrolling: watch::Sender::new(false),
+            outputs: watch::Sender::new(String::new()),
+            snapshots: watch::Sender::new(Vec::new()),
+            active: false,
+            forward: None,
+            config_error: None,
         }
     }
 
-    fn add_to_history(&mut self, id: u32, notification: Notification) {
-        self.history.insert(id, notification);
+    /// Returns the index of the notification in history. A replaced notification keeps its position
+    fn add_to_history(&mut self, id: u32, notification: Notification) -> usize {
+        self.history.insert_full(id, notification).0
+    }
+
+    fn unread_count(&self) -> usize {
+        self.history.values().filter(|notification| !notification.read).count()
+    }
+
+    /// Unread critical notification stays on the bar until it's read or closed
+    fn is_pinned(&self) -> bool {
+        self.visible_on_bar.is_some_and(|index| {
+            let notification = &self.history[index];
+            notification.urgency == Urgency::Critical && !notification.read
+        })
+    }
+
+    fn cancel_expiration(&mut self, id: u32) {
+        if let Some(timer) = self.timers.remove(&id) {
+            timer.abort();
+        }
+    }
+
+    fn cancel_expirations(&mut self) {
+        for (_, timer) in self.timers.drain() {
+            timer.abort();
+        }
+    }
+
+    fn schedule_expiration(&mut self, connection: Connection, id: u32, timeout: Duration) {
+        let timer = tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            if let Ok(server) = connection.object_server().interface::<_, NotificationServer>(OBJECT_PATH).await
+                && let Err(err) = server.get_mut().await.expire(server.signal_emitter(), id).await
+            {
+                eprintln!("Failed to expire notification: {err}");
+            }
+        });
+        self.timers.insert(id, timer.abort_handle());
+        if let Some(notification) = self.history.get_mut(&id) {
+            notification.expires_at = Some(SystemTime::now() + timeout);
+        }
+    }
+
+    async fn expire(&mut self, emitter: &SignalEmitter<'_>, id: u32) -> zbus::Result<()> {
+        if self.apply_expiration(id) {
+            self.emit_closed(emitter, id, CloseReason::Expired).await?;
+        }
+        self.save();
+        self.display_notifications_on_bar();
+        Ok(())
+    }
+
+    /// Removes the expired notification or marks it read, depending on `--on-expire`.
+    /// Returns whether its client still has to be told it's closed
+    fn apply_expiration(&mut self, id: u32) -> bool {
+        self.timers.remove(&id);
+        match self.config.on_expire {
+            ExpireAction::Remove => self.remove(id).is_some_and(|notification| !notification.closed),
+            ExpireAction::MarkRead => {
+                let Some(index) = self.history.get_index_of(&id) else {
+                    return false;
+                };
+                let notification = &mut self.history[index];
+                let was_open = !notification.closed;
+                notification.read = true;
+                notification.closed = true;
+                notification.expires_at = None;
+                if self.visible_on_bar == Some(index) {
+                    self.visible_on_bar = None;
+                }
+                self.forget_forwarded(id);
+                was_open
+            }
+        }
+    }
+
+    /// Adds the notification to history and shows it on the bar, unless Do Not Disturb
+    /// or a pinned notification is in the way. Without an expiration it stays until it's closed
+    fn add_notification(&mut self, id: u32, notification: Notification, expiration: Option<(&Connection, Duration)>) {
+        let critical = notification.urgency == Urgency::Critical;
+        let silent = self.is_silenced(&notification);
+        let pinned = self.is_pinned();
+        self.cancel_expiration(id);
+        let index = self.add_to_history(id, notification);
+        if let Some((connection, timeout)) = expiration {
+            self.schedule_expiration(connection.clone(), id, timeout);
+        }
+        self.save();
+
+        if silent {
+            self.display_notifications_on_bar();
+        } else {
+            if critical || !pinned {
+                self.visible_on_bar = Some(index);
+                if self.is_scrolling_enabled() && !self.fits_on_bar(index) {
+                    self.set_marquee(Some((id, 0)));
+                }
+            }
+            self.new_notification_display();
+        }
+    }
+
+    /// Do Not Disturb only records the notification in history, unless it's critical and those are let through
+    fn is_silenced(&self, notification: &Notification) -> bool {
+        self.dnd && !(notification.urgency == Urgency::Critical && self.config.dnd_bypass_critical)
+    }
+
+    /// A notification observed on its way to another daemon in monitor mode, the id is the one it assigned
+    fn mirror(&mut self, id: u32, notification: Notification) {
+        self.add_notification(id, notification, None);
+    }
+
+    /// The other daemon closed a mirrored notification
+    fn mirror_closed(&mut self, id: u32, reason: u32) {
+        match reason {
+            // The user has seen it in the other daemon
+            reason if reason == CloseReason::Dismissed as u32 => {
+                if let Some(index) = self.history.get_index_of(&id) {
+                    self.mark_read(index);
+                }
+            }
+            // The application took it back
+            reason if reason == CloseReason::Closed as u32 => {
+                self.remove(id);
+            }
+            _ => return,
+        }
+        self.save();
+        self.display_notifications_on_bar();
     }
 
     fn get_notification_list(&self) -> String {
+        let unread = self.unread_count();
         self
             .history
-            .iter()
+            .values()
+            .enumerate()
             .rev()
-            .map(|(_, notification)| {
+            .map(|(index, notification)| {
                 let format = if notification.read {
                     &self.config.read_format
                 } else {
                     &self.config.unread_format
                 };
-                notification.format_with(format)
+                let entry = format.render(&self.placeholders(index, self.config.body_markup, unread));
+                markup::indent_continuation(&entry, &self.config.tooltip_indent)
             })
             .collect::<Vec<_>>()
             .join("\n")
     }
 
+    /// The unread count is passed in, so that rendering the whole history counts only once
+    fn placeholders(&self, index: usize, body_markup: MarkupMode, unread: usize) -> Placeholders<'_> {
+        Placeholders {
+            notification: &self.history[index],
+            config: &self.config,
+            body_markup,
+            unread,
+            total: self.history.len(),
+            position: index + 1,
+        }
+    }
+
+    /// Text of the notification on the bar before it's cut to `bar_max_width`
+    fn full_bar_text(&self, index: usize) -> String {
+        let placeholders = self.placeholders(index, self.config.body_markup.on_bar(), self.unread_count());
+        markup::join_lines(&self.config.bar_format.render(&placeholders), &self.config.bar_line_separator)
+    }
+
     fn bar_text(&self, index: usize) -> String {
-        self.history[index].format_with(&self.config.bar_format)
+        let text = self.full_bar_text(index);
+        match self.config.bar_max_width {
+            0 => text,
+            width => match self.marquee {
+                Some((id, offset)) if self.history.get_index_of(&id) == Some(index) => {
+                    markup::marquee(&text, offset, width, MARQUEE_GAP)
+                }
+                _ => markup::truncate(&text, width, &self.config.ellipsis),
+            },
+        }
+    }
+
+    /// Measured on the whole text, parts of it may repeat, so windows of it can't tell
+    fn fits_on_bar(&self, index: usize) -> bool {
+        markup::width(&markup::plain_text(&self.full_bar_text(index))) <= self.config.bar_max_width
+    }
+
+    fn is_scrolling_enabled(&self) -> bool {
+        self.config.bar_overflow == BarOverflow::Scroll && self.config.bar_max_width > 0
+    }
+
+    /// Moves scrolling text on the bar by one character
+    fn scroll(&mut self) {
+        let Some((id, offset)) = self.marquee else {
+            return;
+        };
+        let Some(index) = self.visible_on_bar.filter(|_| self.visible_id() == Some(id)) else {
+            self.set_marquee(None);
+            return;
+        };
+        // The bar shows the inactive state or a config error instead
+        if !self.active || self.config_error.is_some() {
+            return;
+        }
+        self.set_marquee(Some((id, offset.wrapping_add(1))));
+        let text = self.bar_text(index);
+        // Only the text moves, the rest of the output including a fresh notify class stays
+        let mut waybar_output = self.last_output.clone();
+        waybar_output["text"] = json!(text);
+        self.print(waybar_output);
+    }
+
+    fn set_marquee(&mut self, marquee: Option<(u32, usize)>) {
+        self.marquee = marquee;
+        let scrolling = marquee.is_some();
+        self.scrolling.send_if_modified(|current| std::mem::replace(current, scrolling) != scrolling);
+    }
+
+    fn bar_classes(&self) -> Vec<&'static str> {
+        let mut classes: Vec<_> = self.visible_on_bar
+            .map(|i| self.history[i].urgency.as_str())
+            .into_iter()
+            .collect();
+        if self.dnd {
+            classes.push("dnd");
+        }
+        classes
     }
 
-    fn display_notifications_on_bar(&self) {
+    fn waybar_output(&self, mut classes: Vec<&'static str>) -> serde_json::Value {
+        if !self.active {
+            return json!({
+                "text": "",
+                "tooltip": "Another notification daemon is running, Glance takes over once it exits",
+                "class": ["inactive"],
+                "alt": "inactive",
+                "percentage": 0,
+            });
+        }
         let text = if let Some(i) = self.visible_on_bar { &self.bar_text(i) } else { "" };
-        let waybar_output = json!({
+        classes.extend(self.bar_classes());
+        let mut waybar_output = json!({
             "text": text,
             "tooltip": self.get_notification_list(),
+            "class": classes,
+            "alt": self.bar_alt(),
+            "percentage": self.unread_percentage(),
         });
-        println!("{}", waybar_output);
+        if let Some(err) = &self.config_error {
+            let error = markup::escape(err);
+            waybar_output["text"] = json!("Invalid config");
+            waybar_output["tooltip"] = json!(format!("{error}\n\n{}", waybar_output["tooltip"].as_str().unwrap_or_default()));
+            waybar_output["class"] = json!(["error"]);
+            waybar_output["alt"] = json!("error");
+        }
+        waybar_output
+    }
+
+    /// State of the module for Waybar's `format-icons`
+    fn bar_alt(&self) -> &'static str {
+        let unread = || self.history.values().filter(|notification| !notification.read);
+        if self.dnd {
+            "dnd"
+        } else if unread().any(|notification| notification.urgency == Urgency::Critical) {
+            "critical"
+        } else if unread().next().is_some() {
+            "unread"
+        } else if !self.history.is_empty() {
+            "read"
+        } else {
+            "none"
+        }
+    }
+
+    /// Share of unread notifications in history, 0 when it's empty
+    fn unread_percentage(&self) -> usize {
+        match self.history.len() {
+            0 => 0,
+            total => self.unread_count() * 100 / total,
+        }
+    }
+
+    fn print(&mut self, waybar_output: serde_json::Value) {
+        let line = waybar_output.to_string();
+        println!("{line}");
+        self.outputs.send_replace(line);
+        self.last_output = waybar_output;
+    }
+
+    fn display_notifications_on_bar(&mut self) {
+        self.print(self.waybar_output(vec![]));
     }
     
-    fn new_notification_display(&self) {
-        let text = if let Some(i) = self.visible_on_bar { &self.bar_text(i) } else { "" };
-        let waybar_output = json!({
-            "text": text,
-            "tooltip": self.get_notification_list(),
-            "class": "notify"
-        });
-        println!("{}", waybar_output);
+    fn new_notification_display(&mut self) {
+        self.print(self.waybar_output(vec!["notify"]));
+    }
+
+    /// Whether the output changes over time even if nothing else happens
+    fn is_time_dependent(&self) -> bool {
+        [&self.config.read_format, &self.config.unread_format, &self.config.bar_format]
+            .iter()
+            .any(|format| format.uses("age"))
+    }
+
+    /// How often to re-render, `None` when nothing changes over time
+    fn refresh_period(&self) -> Option<Duration> {
+        let interval = Duration::from_secs(self.config.refresh_interval);
+        (!interval.is_zero() && self.is_time_dependent()).then_some(interval)
+    }
+
+    /// How often scrolling text moves, `None` when it doesn't scroll
+    fn scroll_period(&self) -> Option<Duration> {
+        self.is_scrolling_enabled()
+            .then(|| Duration::from_millis(self.config.scroll_interval.max(1)))
+    }
+
+    /// Periodic re-render, prints only if e.g. the age of a notification changed since the last output
+    fn refresh(&mut self) {
+        let waybar_output = self.waybar_output(vec![]);
+        // Classes don't depend on time, so a leftover notify class isn't a reason to print
+        if waybar_output["text"] != self.last_output["text"] || waybar_output["tooltip"] != self.last_output["tooltip"] {
+            self.print(waybar_output);
+        }
+    }
+
+    /// Picks up the saved history when Glance becomes the active daemon. A waiting instance leaves the
+    /// state file to the daemon that owns the name, and its notifications don't expire meanwhile
+    fn set_active(&mut self, connection: &Connection, active: bool) {
+        if active && !self.active {
+            if self.config.persist {
+                self.cancel_expirations();
+                self.restore();
+            }
+            self.restore_expirations(connection);
+        } else if !active {
+            self.cancel_expirations();
+        }
+        self.active = active;
+        self.display_notifications_on_bar();
+    }
+
+    fn set_dnd(&mut self, dnd: bool) {
+        self.dnd = dnd;
+        self.display_notifications_on_bar();
     }
 
     fn new_id(&mut self) -> u32 {
@@ -110,6 +783,137 @@ impl NotificationServer {
         self.last_notification_id
     }
 
+    fn visible_id(&self) -> Option<u32> {
+        self.visible_on_bar
+            .and_then(|index| self.history.get_index(index))
+            .map(|(id, _)| *id)
+    }
+
+    fn remove(&mut self, id: u32) -> Option<Notification> {
+        self.cancel_expiration(id);
+        self.forget_forwarded(id);
+        let (index, _, notification) = self.history.shift_remove_full(&id)?;
+        match self.visible_on_bar {
+            // Keep the same notification on the bar when an older one goes away
+            Some(visible) if index < visible => self.visible_on_bar = Some(visible - 1),
+            Some(visible) if visible >= self.history.len() => {
+                self.visible_on_bar = self.history.len().checked_sub(1);
+            }
+            _ => {}
+        }
+        Some(notification)
+    }
+
+    async fn close(
+        &mut self,
+        emitter: &SignalEmitter<'_>,
+        id: u32,
+        reason: CloseReason,
+    ) -> zbus::Result<()> {
+        if let Some(notification) = self.remove(id)
+            && !notification.closed
+        {
+            self.emit_closed(emitter, id, reason).await?;
+        }
+        Ok(())
+    }
+
+    /// While another daemon owns the name, the id may refer to one of its notifications
+    async fn emit_closed(&self, emitter: &SignalEmitter<'_>, id: u32, reason: CloseReason) -> zbus::Result<()> {
+        if self.active {
+            Self::notification_closed(emitter, id, reason as u32).await?;
+        }
+        Ok(())
+    }
+
+    /// Signals about the notification go only to the application that sent it
+    fn client_emitter<'a>(&self, emitter: &SignalEmitter<'a>, id: u32) -> SignalEmitter<'a> {
+        match self.history.get(&id).and_then(|notification| notification.sender.as_ref()) {
+            Some(sender) => emitter.to_owned().set_destination(sender.clone().into_inner().into()),
+            None => emitter.to_owned(),
+        }
+    }
+
+    async fn invoke_action(
+        &mut self,
+        emitter: &SignalEmitter<'_>,
+        key: Option<&str>,
+        activation_token: Option<&str>,
+    ) -> zbus::Result<()> {
+        let Some(id) = self.visible_id() else {
+            return Ok(());
+        };
+        let notification = &self.history[&id];
+        let Some(action) = notification.action(key).map(str::to_string) else {
+            return Ok(());
+        };
+        let resident = notification.resident;
+
+        let sender_emitter = self.client_emitter(emitter, id);
+        if let Some(token) = activation_token {
+            Self::activation_token(&sender_emitter, id, token).await?;
+        }
+        Self::action_invoked(&sender_emitter, id, &action).await?;
+
+        if resident {
+            self.mark_read_and_render();
+        } else {
+            self.close(emitter, id, CloseReason::Dismissed).await?;
+            self.save();
+            self.display_notifications_on_bar();
+        }
+        Ok(())
+    }
+
+    /// Opens a link from the visible notification with the configured opener
+    fn open_link(&self, index: usize) -> std::io::Result<()> {
+        let link = self
+            .visible_id()
+            .and_then(|id| self.history[&id].links.get(index))
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no such link"))?;
+        if !is_safe_link(link) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("only http, https and mailto links can be opened, got {link}"),
+            ));
+        }
+        let mut opener = self.config.opener.split_whitespace();
+        let program = opener
+            .next()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "opener is empty"))?;
+        let mut child = tokio::process::Command::new(program)
+            .args(opener)
+            .arg(link)
+            .stdout(std::process::Stdio::null())
+            .spawn()?;
+        tokio::spawn(async move { child.wait().await });
+        Ok(())
+    }
+
+    /// The user closes the notification visible on the bar
+    async fn dismiss(&mut self, emitter: &SignalEmitter<'_>) -> zbus::Result<()> {
+        if let Some(id) = self.visible_id() {
+            self.close(emitter, id, CloseReason::Dismissed).await?;
+        }
+        self.save();
+        self.display_notifications_on_bar();
+        Ok(())
+    }
+
+    async fn clear_all(&mut self, emitter: &SignalEmitter<'_>) -> zbus::Result<()> {
+        self.cancel_expirations();
+        self.visible_on_bar = None;
+        for (id, notification) in std::mem::take(&mut self.history) {
+            self.forget_forwarded(id);
+            if !notification.closed {
+                self.emit_closed(emitter, id, CloseReason::Dismissed).await?;
+            }
+        }
+        self.save();
+        self.display_notifications_on_bar();
+        Ok(())
+    }
+
     fn previous_notification(&mut self) {
         if self.history.is_empty() {
             return;
@@ -130,6 +934,7 @@ impl NotificationServer {
             }
         }
 
+        self.save();
         self.display_notifications_on_bar();
     }
 
@@ -153,17 +958,29 @@ impl NotificationServer {
             }
         }
 
+        self.save();
         self.display_notifications_on_bar();
     }
     
     fn mark_read(&mut self, index: usize) {
             self.history[index].read = true;
+            self.set_marquee(None);
     }
 
     fn mark_read_and_render(&mut self) {
         if let Some(index) = self.visible_on_bar {
             self.mark_read(index);
         }
+        self.save();
+        self.display_notifications_on_bar();
+    }
+
+    fn mark_all_read_and_render(&mut self) {
+        for notification in self.history.values_mut() {
+            notification.read = true;
+        }
+        self.set_marquee(None);
+        self.save();
         self.display_notifications_on_bar();
     }
 }
@@ -171,8 +988,11 @@ impl NotificationServer {
 
 #[zbus::interface(name = "org.freedesktop.Notifications")]
 impl NotificationServer {
+    #[allow(clippy::too_many_arguments, unused_variables)]
     fn notify(
         &mut self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
         app_name: &str,
         replaces_id: u32,
         app_icon: &str,
@@ -182,38 +1002,60 @@ impl NotificationServer {
         hints: HashMap<String, Value>,
         expire_timeout: i32,
     ) -> u32 {
-        let notification = Notification {
-            app_name: app_name.to_string(),
-            summary: summary.to_string(),
-            body: body.to_string(),
-            read: false,
-        };
+        let sender = header.sender().map(|sender| sender.to_owned().into());
+        let notification = Notification::new(app_name, summary, body, actions.clone(), &hints, sender);
         let id = if replaces_id == 0 { self.new_id() } else { replaces_id };
-        self.add_to_history(id, notification);
-        self.visible_on_bar = Some(self.history.len() - 1);
-        self.new_notification_display();
-
+        let timeout = match expire_timeout {
+            // Critical notifications shouldn't expire automatically according to the spec
+            -1 if notification.urgency == Urgency::Critical => 0,
+            -1 => self.config.default_timeout,
+            timeout => timeout.max(0) as u32,
+        };
+        let expiration = (timeout > 0).then(|| (connection, Duration::from_millis(timeout.into())));
+        self.add_notification(id, notification, expiration);
+        if self.forward.is_some() {
+            let notify = forward::Notify {
+                app_name: app_name.to_string(),
+                app_icon: app_icon.to_string(),
+                summary: summary.to_string(),
+                body: body.to_string(),
+                actions,
+                hints: hints
+                    .into_iter()
+                    .filter_map(|(key, value)| Some((key, value.try_to_owned().ok()?)))
+                    .collect(),
+                expire_timeout,
+            };
+            self.forward_notify(connection, id, notify);
+        }
         id
     }
 
     fn get_capabilities(&self) -> Vec<&str> {
-        vec!["body", "actions"]
+        let mut capabilities = vec!["body", "actions"];
+        if self.config.body_markup == MarkupMode::Sanitize {
+            capabilities.extend(["body-markup", "body-hyperlinks"]);
+        }
+        capabilities
     }
 
-    fn close_notification(&mut self, id: u32) -> zbus::fdo::Result<()> {
+    async fn close_notification(
+        &mut self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        id: u32,
+    ) -> zbus::fdo::Result<()> {
         if id == 0 {
-            // Well, that violates spec. Could use signals instead
-            // id=0 shouldn't be used anyway according to the spec so it sounds reasonable
-            self.history.shift_remove_index(self.visible_on_bar.unwrap());
+            // Well, that violates spec. Prefer Close from the io.github.piwonskp.Glance1 interface
+            // id=0 shouldn't be used anyway according to the spec so it's kept for compatibility
+            self.dismiss(&emitter).await?;
         } else {
-            self.history.shift_remove(&id);
-        }
-        if self.visible_on_bar >= Some(self.history.len()) {
-            self.visible_on_bar = if self.history.len() == 0 { None } else {
-                Some(self.history.len() - 1)
-            };
+            if !self.history.contains_key(&id) {
+                return Err(zbus::fdo::Error::Failed(format!("No notification with id {id}")));
+            }
+            self.close(&emitter, id, CloseReason::Closed).await?;
+            self.save();
+            self.display_notifications_on_bar();
         }
-        self.display_notifications_on_bar();
         Ok(())
     }
 
@@ -225,39 +1067,364 @@ impl NotificationServer {
             "1.3",
         )
     }
+
+    #[zbus(signal)]
+    async fn notification_closed(emitter: &SignalEmitter<'_>, id: u32, reason: u32) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn action_invoked(emitter: &SignalEmitter<'_>, id: u32, action_key: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn activation_token(emitter: &SignalEmitter<'_>, id: u32, activation_token: &str) -> zbus::Result<()>;
 }
 
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Ctl { command }) => {
+            if let Err(err) = ctl::run(command).await {
+                eprintln!("glance: {err}");
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(Command::Bar) => {
+            if let Err(err) = bar::run().await {
+                eprintln!("glance: {err}");
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(Command::Daemon(config)) => daemon(*config).await,
+        None => daemon(cli.config).await,
+    }
+}
+
+/// Interval ticking with the period. Without one its select branch is disabled, so the period doesn't matter
+fn ticker(period: Option<Duration>, behavior: tokio::time::MissedTickBehavior) -> tokio::time::Interval {
+    let mut interval = tokio::time::interval(period.unwrap_or(Duration::from_secs(1)));
+    interval.set_missed_tick_behavior(behavior);
+    interval
+}
+
+async fn daemon(cli_config: NotificationConfig) -> Result<()> {
     std::io::stdout().flush().unwrap();
+    let cli_args = config::daemon_args();
+    // A broken config file shouldn't leave the bar empty, the error is shown there instead
+    let (config, config_error) = match config::load(&cli_args) {
+        Ok(config) => (config, None),
+        Err(err) => {
+            eprintln!("Failed to load config: {err}");
+            (cli_config, Some(err))
+        }
+    };
     let connection = Connection::session().await?;
     let server = connection.object_server();
-    server.at("/org/freedesktop/Notifications", NotificationServer::new()).await?;
-    connection.request_name("org.freedesktop.Notifications").await?;
+    let mut notification_server = NotificationServer::new(config);
+    notification_server.config_error = config_error;
+    let mut refresh_period = notification_server.refresh_period();
+    let mut scroll_period = notification_server.scroll_period();
+    let mut outputs = notification_server.outputs.subscribe();
+    let mut scrolling = notification_server.scrolling.subscribe();
+    let monitor = notification_server.config.monitor;
+    if notification_server.config.persist {
+        tokio::spawn(state::write_snapshots(notification_server.snapshots.subscribe()));
+    }
+    if let Some(destination) = notification_server.config.forward_to.clone() {
+        let min_urgency = notification_server.config.forward_min_urgency;
+        notification_server.forward = Some(Forward::new(&connection, destination, min_urgency).await?);
+    }
+    let name_flags = if notification_server.config.replace {
+        RequestNameFlags::AllowReplacement | RequestNameFlags::ReplaceExisting
+    } else {
+        RequestNameFlags::AllowReplacement.into()
+    };
+    server.at(OBJECT_PATH, notification_server).await?;
+    server.at(OBJECT_PATH, Control).await?;
+
+    // Another daemon may own the name or take it over later, Glance waits in the queue meanwhile
+    let dbus = DBusProxy::new(&connection).await?;
+    let mut name_acquired = dbus.receive_name_acquired().await?;
+    let mut name_lost = dbus.receive_name_lost().await?;
+    let active = if monitor {
+        let connection = connection.clone();
+        tokio::spawn(async move {
+            if let Err(err) = monitor::run(connection).await {
+                eprintln!("Failed to monitor notifications: {err}");
+            }
+        });
+        true
+    } else {
+        let reply = connection.request_name_with_flags(NOTIFICATIONS_NAME, name_flags).await?;
+        let active = matches!(reply, RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner);
+        if !active {
+            eprintln!("{NOTIFICATIONS_NAME} is owned by another daemon, waiting for it to exit. Pass --replace to take it over");
+        }
+        active
+    };
+    connection.request_name_with_flags(CONTROL_NAME, name_flags).await?;
+
+    if let Ok(server) = server.interface::<_, NotificationServer>(OBJECT_PATH).await {
+        let mut server = server.get_mut().await;
+        server.set_active(&connection, active);
+        if server.forward.is_some() {
+            let connection = connection.clone();
+            tokio::spawn(async move {
+                if let Err(err) = forward::relay(connection).await {
+                    eprintln!("Failed to relay signals of the forwarded notifications: {err}");
+                }
+            });
+        }
+    }
 
     let sigrtmin = libc::SIGRTMIN();
     let mut signal_mark_read = signal(SignalKind::from_raw(sigrtmin))?;
     let mut signal_previous = signal(SignalKind::from_raw(sigrtmin + 2))?;
     let mut signal_next = signal(SignalKind::from_raw(sigrtmin + 3))?;
+    let mut signal_invoke_action = signal(SignalKind::from_raw(sigrtmin + 4))?;
+    let mut signal_clear_all = signal(SignalKind::from_raw(sigrtmin + 5))?;
+    let mut signal_mark_all_read = signal(SignalKind::from_raw(sigrtmin + 6))?;
+    let mut signal_reload = signal(SignalKind::hangup())?;
+    let mut refresh = ticker(refresh_period, tokio::time::MissedTickBehavior::Delay);
+    let mut scroll = ticker(scroll_period, tokio::time::MissedTickBehavior::Skip);
+    let mut config_check = tokio::time::interval(CONFIG_CHECK_INTERVAL);
+    let mut config_modified = config::modified();
 
     loop {
+        let mut reload = false;
         tokio::select! {
             _ = signal_mark_read.recv() => {
-                if let Ok(server) = server.interface::<_, NotificationServer>("/org/freedesktop/Notifications").await {
+                if let Ok(server) = server.interface::<_, NotificationServer>(OBJECT_PATH).await {
                     server.get_mut().await.mark_read_and_render();
                 }
             },
             _ = signal_previous.recv() => {
-                if let Ok(server) = server.interface::<_, NotificationServer>("/org/freedesktop/Notifications").await {
+                if let Ok(server) = server.interface::<_, NotificationServer>(OBJECT_PATH).await {
                     server.get_mut().await.previous_notification();
                 }
             },
             _ = signal_next.recv() => {
-                if let Ok(server) = server.interface::<_, NotificationServer>("/org/freedesktop/Notifications").await {
+                if let Ok(server) = server.interface::<_, NotificationServer>(OBJECT_PATH).await {
                     server.get_mut().await.next_notification();
                 }
             },
+            _ = signal_invoke_action.recv() => {
+                if let Ok(server) = server.interface::<_, NotificationServer>(OBJECT_PATH).await
+                    && let Err(err) = server.get_mut().await.invoke_action(server.signal_emitter(), None, None).await
+                {
+                    eprintln!("Failed to invoke action: {err}");
+                }
+            },
+            _ = signal_clear_all.recv() => {
+                if let Ok(server) = server.interface::<_, NotificationServer>(OBJECT_PATH).await
+                    && let Err(err) = server.get_mut().await.clear_all(server.signal_emitter()).await
+                {
+                    eprintln!("Failed to clear notifications: {err}");
+                }
+            },
+            _ = signal_mark_all_read.recv() => {
+                if let Ok(server) = server.interface::<_, NotificationServer>(OBJECT_PATH).await {
+                    server.get_mut().await.mark_all_read_and_render();
+                }
+            },
+            _ = signal_reload.recv() => reload = true,
+            _ = config_check.tick() => {
+                let modified = config::modified();
+                if modified != config_modified {
+                    config_modified = modified;
+                    reload = true;
+                }
+            },
+            _ = refresh.tick(), if refresh_period.is_some() => {
+                if let Ok(server) = server.interface::<_, NotificationServer>(OBJECT_PATH).await {
+                    server.get_mut().await.refresh();
+                }
+            },
+            Some(signal) = name_acquired.next() => {
+                if signal.args().is_ok_and(|args| args.name() == NOTIFICATIONS_NAME)
+                    && let Ok(server) = server.interface::<_, NotificationServer>(OBJECT_PATH).await
+                {
+                    server.get_mut().await.set_active(&connection, true);
+                }
+            },
+            Some(signal) = name_lost.next() => {
+                if signal.args().is_ok_and(|args| args.name() == NOTIFICATIONS_NAME)
+                    && let Ok(server) = server.interface::<_, NotificationServer>(OBJECT_PATH).await
+                {
+                    server.get_mut().await.set_active(&connection, false);
+                }
+            },
+            Ok(()) = outputs.changed() => {
+                let output = outputs.borrow_and_update().clone();
+                if let Ok(control) = server.interface::<_, Control>(OBJECT_PATH).await
+                    && let Err(err) = Control::output_changed(control.signal_emitter(), &output).await
+                {
+                    eprintln!("Failed to broadcast output: {err}");
+                }
+            },
+            // Wakes the loop up, so that the scroll branch below is enabled or disabled
+            Ok(()) = scrolling.changed() => {
+                if *scrolling.borrow_and_update() {
+                    scroll.reset();
+                }
+            },
+            _ = scroll.tick(), if scroll_period.is_some() && *scrolling.borrow() => {
+                if let Ok(server) = server.interface::<_, NotificationServer>(OBJECT_PATH).await {
+                    server.get_mut().await.scroll();
+                }
+            },
+        }
+        if reload && let Ok(server) = server.interface::<_, NotificationServer>(OBJECT_PATH).await {
+            let mut server = server.get_mut().await;
+            server.reload_config(&cli_args);
+            refresh_period = server.refresh_period();
+            scroll_period = server.scroll_period();
+            refresh = ticker(refresh_period, tokio::time::MissedTickBehavior::Delay);
+            scroll = ticker(scroll_period, tokio::time::MissedTickBehavior::Skip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(args: &[&str]) -> NotificationServer {
+        let cli = Cli::parse_from(std::iter::once("glance").chain(args.iter().copied()));
+        let mut server = NotificationServer::new(cli.config);
+        server.active = true;
+        server
+    }
+
+    fn add(server: &mut NotificationServer, id: u32, summary: &str, urgency: Urgency) {
+        let mut notification = Notification::new("app", summary, "", Vec::new(), &HashMap::new(), None);
+        notification.urgency = urgency;
+        server.add_notification(id, notification, None);
+    }
+
+    fn ids(server: &NotificationServer) -> Vec<u32> {
+        server.history.keys().copied().collect()
+    }
+
+    #[test]
+    fn removing_older_notification_keeps_visible_one() {
+        let mut server = server(&[]);
+        for id in 1..=3 {
+            add(&mut server, id, "hi", Urgency::Normal);
+        }
+        server.visible_on_bar = Some(1);
+        server.remove(1);
+        assert_eq!(ids(&server), [2, 3]);
+        assert_eq!(server.visible_id(), Some(2));
+    }
+
+    #[test]
+    fn removing_newer_notification_keeps_visible_one() {
+        let mut server = server(&[]);
+        for id in 1..=3 {
+            add(&mut server, id, "hi", Urgency::Normal);
         }
+        server.visible_on_bar = Some(1);
+        server.remove(3);
+        assert_eq!(server.visible_id(), Some(2));
+    }
+
+    #[test]
+    fn removing_visible_notification_shows_next_one() {
+        let mut server = server(&[]);
+        for id in 1..=3 {
+            add(&mut server, id, "hi", Urgency::Normal);
+        }
+        server.visible_on_bar = Some(1);
+        server.remove(2);
+        assert_eq!(server.visible_id(), Some(3));
+        // The newest one is followed by the one before it
+        server.remove(3);
+        assert_eq!(server.visible_id(), Some(1));
+        server.remove(1);
+        assert_eq!(server.visible_on_bar, None);
+    }
+
+    #[test]
+    fn expiring_removes_notification() {
+        let mut server = server(&[]);
+        add(&mut server, 1, "old", Urgency::Normal);
+        add(&mut server, 2, "new", Urgency::Normal);
+        assert!(server.apply_expiration(2));
+        assert_eq!(ids(&server), [1]);
+        assert_eq!(server.visible_id(), Some(1));
+        assert!(!server.apply_expiration(2));
+    }
+
+    #[test]
+    fn expiring_with_mark_read_clears_bar() {
+        let mut server = server(&["--on-expire", "mark-read"]);
+        add(&mut server, 1, "old", Urgency::Normal);
+        add(&mut server, 2, "new", Urgency::Normal);
+        assert!(server.apply_expiration(2));
+        assert_eq!(ids(&server), [1, 2]);
+        assert_eq!(server.visible_on_bar, None);
+        let notification = &server.history[&2];
+        assert!(notification.read && notification.closed);
+        // The client was told already
+        assert!(!server.apply_expiration(2));
+    }
+
+    #[test]
+    fn expiring_hidden_notification_with_mark_read_keeps_bar() {
+        let mut server = server(&["--on-expire", "mark-read"]);
+        add(&mut server, 1, "old", Urgency::Normal);
+        add(&mut server, 2, "new", Urgency::Normal);
+        server.apply_expiration(1);
+        assert_eq!(server.visible_id(), Some(2));
+    }
+
+    #[test]
+    fn pinned_critical_notification_stays_on_bar() {
+        let mut server = server(&[]);
+        add(&mut server, 1, "battery low", Urgency::Critical);
+        add(&mut server, 2, "chat", Urgency::Normal);
+        add(&mut server, 3, "mail", Urgency::Low);
+        assert_eq!(server.visible_id(), Some(1));
+        assert_eq!(server.unread_count(), 3);
+    }
+
+    #[test]
+    fn new_critical_notification_replaces_pinned_one() {
+        let mut server = server(&[]);
+        add(&mut server, 1, "battery low", Urgency::Critical);
+        add(&mut server, 2, "disk full", Urgency::Critical);
+        assert_eq!(server.visible_id(), Some(2));
+    }
+
+    #[test]
+    fn read_critical_notification_is_unpinned() {
+        let mut server = server(&[]);
+        add(&mut server, 1, "battery low", Urgency::Critical);
+        server.mark_read_and_render();
+        add(&mut server, 2, "chat", Urgency::Normal);
+        assert_eq!(server.visible_id(), Some(2));
+    }
+
+    #[test]
+    fn navigation_works_while_pinned() {
+        let mut server = server(&[]);
+        add(&mut server, 1, "chat", Urgency::Normal);
+        add(&mut server, 2, "battery low", Urgency::Critical);
+        add(&mut server, 3, "mail", Urgency::Normal);
+        server.previous_notification();
+        assert_eq!(server.visible_id(), Some(1));
+        server.next_notification();
+        server.next_notification();
+        assert_eq!(server.visible_id(), Some(3));
+    }
+
+    #[test]
+    fn placeholders_in_content_are_not_substituted() {
+        let mut server = server(&["--bar-format", "{unread}: {summary}"]);
+        add(&mut server, 1, "{unread} new {total}", Urgency::Normal);
+        assert_eq!(server.bar_text(0), "1: {unread} new {total}");
     }
 }
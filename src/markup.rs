@@ -0,0 +1,606 @@
+use std::borrow::Cow;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// How markup in notification content is treated before it's put into the Pango formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum MarkupMode {
+    /// Show the content literally, tags included
+    Escape,
+    /// Remove tags and keep the text
+    Strip,
+    /// Keep the tags allowed by the notification spec: <b>, <i>, <u> and <a>, escape everything else
+    Sanitize,
+    /// Insert the content as is. Broken markup breaks rendering of the whole module
+    Raw,
+}
+
+impl MarkupMode {
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        match self {
+            MarkupMode::Escape => escape(text),
+            MarkupMode::Strip => Cow::Owned(escape(&plain_text(text)).into_owned()),
+            MarkupMode::Sanitize => Cow::Owned(sanitize(text)),
+            MarkupMode::Raw => Cow::Borrowed(text),
+        }
+    }
+
+    /// Links and formatting can't be used on the bar, so sanitized markup becomes plain text there
+    pub fn on_bar(self) -> Self {
+        match self {
+            MarkupMode::Sanitize => MarkupMode::Strip,
+            mode => mode,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Token<'a> {
+    /// Text with entities already decoded
+    Text(Cow<'a, str>),
+    Open {
+        name: String,
+        attributes: Vec<(String, String)>,
+        self_closing: bool,
+    },
+    Close(String),
+}
+
+impl Token<'_> {
+    fn attribute(&self, attribute: &str) -> Option<&str> {
+        match self {
+            Token::Open { attributes, .. } => attributes
+                .iter()
+                .find(|(name, _)| name == attribute)
+                .map(|(_, value)| value.as_str()),
+            _ => None,
+        }
+    }
+}
+
+/// Splits markup into text and tags. Anything that doesn't look like a tag, e.g. "a < b", is text
+fn tokenize(input: &str) -> Vec<Token<'_>> {
+    spans(input).into_iter().map(|(token, _)| token).collect()
+}
+
+/// Tokens along with the part of the input they were parsed from
+fn spans(input: &str) -> Vec<(Token<'_>, &str)> {
+    let mut spans = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        if let Some((token, length)) = parse_tag(rest) {
+            spans.push((token, &rest[..length]));
+            rest = &rest[length..];
+            continue;
+        }
+        let first = rest.chars().next().map_or(1, char::len_utf8);
+        let end = rest[first..].find('<').map_or(rest.len(), |index| index + first);
+        spans.push((Token::Text(decode_entities(&rest[..end])), &rest[..end]));
+        rest = &rest[end..];
+    }
+    spans
+}
+
+/// Parses a tag at the beginning of the input, returns it with its length
+fn parse_tag(input: &str) -> Option<(Token<'static>, usize)> {
+    let bytes = input.as_bytes();
+    if bytes.first() != Some(&b'<') {
+        return None;
+    }
+    let mut position = 1;
+    let closing = bytes.get(position) == Some(&b'/');
+    if closing {
+        position += 1;
+    }
+
+    let name_start = position;
+    if !bytes.get(position)?.is_ascii_alphabetic() {
+        return None;
+    }
+    while bytes.get(position).is_some_and(u8::is_ascii_alphanumeric) {
+        position += 1;
+    }
+    let name = input[name_start..position].to_ascii_lowercase();
+
+    let mut attributes = Vec::new();
+    loop {
+        let whitespace_start = position;
+        while bytes.get(position).is_some_and(u8::is_ascii_whitespace) {
+            position += 1;
+        }
+        match bytes.get(position)? {
+            b'>' if closing => return Some((Token::Close(name), position + 1)),
+            b'>' => {
+                let token = Token::Open { name, attributes, self_closing: false };
+                return Some((token, position + 1));
+            }
+            b'/' if !closing && bytes.get(position + 1) == Some(&b'>') => {
+                let token = Token::Open { name, attributes, self_closing: true };
+                return Some((token, position + 2));
+            }
+            // Attributes have to be separated from the tag name and each other
+            _ if closing || position == whitespace_start => return None,
+            _ => {
+                let (attribute, length) = parse_attribute(&input[position..])?;
+                attributes.push(attribute);
+                position += length;
+            }
+        }
+    }
+}
+
+/// Parses `name`, `name=value`, `name="value"` or `name='value'`
+fn parse_attribute(input: &str) -> Option<((String, String), usize)> {
+    let bytes = input.as_bytes();
+    let mut position = 0;
+    while bytes
+        .get(position)
+        .is_some_and(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b':'))
+    {
+        position += 1;
+    }
+    if position == 0 {
+        return None;
+    }
+    let name = input[..position].to_ascii_lowercase();
+
+    let mut lookahead = position;
+    while bytes.get(lookahead).is_some_and(u8::is_ascii_whitespace) {
+        lookahead += 1;
+    }
+    if bytes.get(lookahead) != Some(&b'=') {
+        return Some(((name, String::new()), position));
+    }
+    position = lookahead + 1;
+    while bytes.get(position).is_some_and(u8::is_ascii_whitespace) {
+        position += 1;
+    }
+
+    let (value, end) = match bytes.get(position)? {
+        quote @ (b'"' | b'\'') => {
+            let length = input[position + 1..].find(*quote as char)?;
+            let value_start = position + 1;
+            (&input[value_start..value_start + length], value_start + length + 1)
+        }
+        _ => {
+            let length = input[position..]
+                .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                .unwrap_or(input.len() - position);
+            (&input[position..position + length], position + length)
+        }
+    };
+    Some(((name, decode_entities(value).into_owned()), end))
+}
+
+fn decode_entities(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        // Entities are short, no need to look for the semicolon any further
+        let entity = rest
+            .bytes()
+            .take(12)
+            .position(|byte| byte == b';')
+            .and_then(|end| Some((decode_entity(&rest[1..end])?, end)));
+        match entity {
+            Some((character, end)) => {
+                decoded.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    Cow::Owned(decoded)
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        _ => {
+            let number = entity.strip_prefix('#')?;
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+/// Escapes text so that it's shown literally in Pango markup
+pub fn escape(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(character),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Text content of the markup, images are replaced with their alternative text
+pub fn plain_text(input: &str) -> String {
+    let mut text = String::new();
+    for token in tokenize(input) {
+        match &token {
+            Token::Text(content) => text.push_str(content),
+            Token::Open { name, .. } if name == "img" => text.push_str(token.attribute("alt").unwrap_or_default()),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Targets of the `<a href="...">` links
+pub fn links(input: &str) -> Vec<String> {
+    tokenize(input)
+        .iter()
+        .filter(|token| matches!(token, Token::Open { name, .. } if name == "a"))
+        .filter_map(|token| token.attribute("href"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Keeps the tags allowed by the spec, drops any other tag and escapes the text.
+/// The result is always balanced, so a stray closing tag can't break the surrounding format
+fn sanitize(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut open_tags: Vec<String> = Vec::new();
+    for token in tokenize(input) {
+        match &token {
+            Token::Text(text) => output.push_str(&escape(text)),
+            Token::Open { name, self_closing: false, .. } if matches!(name.as_str(), "b" | "i" | "u") => {
+                output.push_str(&format!("<{name}>"));
+                open_tags.push(name.clone());
+            }
+            Token::Open { name, self_closing: false, .. } if name == "a" => {
+                // A link without a target is just text
+                if let Some(href) = token.attribute("href") {
+                    output.push_str(&format!("<a href=\"{}\">", escape(href)));
+                    open_tags.push(name.clone());
+                }
+            }
+            // Images can't be shown in Pango markup
+            Token::Open { name, .. } if name == "img" => {
+                output.push_str(&escape(token.attribute("alt").unwrap_or_default()));
+            }
+            Token::Close(name) if open_tags.contains(name) => {
+                // Closes whatever was left open inside, so that tags are always properly nested
+                while let Some(open) = open_tags.pop() {
+                    output.push_str(&format!("</{open}>"));
+                    if &open == name {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    for open in open_tags.iter().rev() {
+        output.push_str(&format!("</{open}>"));
+    }
+    output
+}
+
+/// Transforms the text of the markup, leaving the tags intact
+pub fn map_text(input: &str, transform: impl Fn(&str) -> String) -> String {
+    let mut output = String::with_capacity(input.len());
+    for (token, source) in spans(input) {
+        match token {
+            Token::Text(text) => output.push_str(&escape(&transform(&text))),
+            _ => output.push_str(source),
+        }
+    }
+    output
+}
+
+/// Joins the lines of the markup with the separator, blank lines are dropped
+pub fn join_lines(input: &str, separator: &str) -> String {
+    if !input.contains(['\n', '\r']) {
+        return input.to_string();
+    }
+    input
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(&escape(separator))
+}
+
+/// Indents every line of the markup but the first one
+pub fn indent_continuation(input: &str, indent: &str) -> String {
+    let indent = escape(indent);
+    input
+        .lines()
+        .enumerate()
+        .map(|(number, line)| if number == 0 { line.to_string() } else { format!("{indent}{line}") })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Display width of the text in terminal-like columns, e.g. CJK characters and most emoji take two
+pub fn width(text: &str) -> usize {
+    text.graphemes(true).map(UnicodeWidthStr::width).sum()
+}
+
+/// Shortens the text of the markup to at most `max_width` columns, ellipsis included.
+/// Grapheme clusters are never split and tags that were open at the cut are closed
+pub fn truncate(input: &str, max_width: usize, ellipsis: &str) -> String {
+    let spans = spans(input);
+    let total: usize = spans
+        .iter()
+        .map(|(token, _)| match token {
+            Token::Text(text) => width(text),
+            _ => 0,
+        })
+        .sum();
+    if total <= max_width {
+        return input.to_string();
+    }
+    let ellipsis_width = width(ellipsis);
+    let ellipsis = if ellipsis_width <= max_width { ellipsis } else { "" };
+    let mut remaining = max_width - width(ellipsis);
+
+    let mut output = String::with_capacity(input.len());
+    let mut open_tags: Vec<String> = Vec::new();
+    for (token, source) in spans {
+        match &token {
+            Token::Text(text) => {
+                let text_width = width(text);
+                if text_width > remaining {
+                    let mut kept = String::new();
+                    for grapheme in text.graphemes(true) {
+                        let grapheme_width = grapheme.width();
+                        if grapheme_width > remaining {
+                            break;
+                        }
+                        kept.push_str(grapheme);
+                        remaining -= grapheme_width;
+                    }
+                    output.push_str(&escape(&kept));
+                    output.push_str(&escape(ellipsis));
+                    break;
+                }
+                output.push_str(source);
+                remaining -= text_width;
+            }
+            Token::Open { name, self_closing: false, .. } => {
+                output.push_str(source);
+                open_tags.push(name.clone());
+            }
+            Token::Close(name) => {
+                output.push_str(source);
+                if let Some(index) = open_tags.iter().rposition(|open| open == name) {
+                    open_tags.truncate(index);
+                }
+            }
+            Token::Open { .. } => output.push_str(source),
+        }
+    }
+    for open in open_tags.iter().rev() {
+        output.push_str(&format!("</{open}>"));
+    }
+    output
+}
+
+/// A window of `max_width` columns into the markup scrolled by `offset` grapheme clusters.
+/// The text wraps around after `gap`, tags are reopened for the text they apply to
+pub fn marquee(input: &str, offset: usize, max_width: usize, gap: &str) -> String {
+    let spans = spans(input);
+    // Each grapheme cluster along with the tags open around it
+    let mut graphemes: Vec<(&str, Vec<(&str, &str)>)> = Vec::new();
+    let mut open_tags: Vec<(&str, &str)> = Vec::new();
+    for (token, source) in &spans {
+        match token {
+            Token::Text(text) => graphemes.extend(text.graphemes(true).map(|grapheme| (grapheme, open_tags.clone()))),
+            Token::Open { name, self_closing: false, .. } => open_tags.push((name, source)),
+            Token::Close(name) => {
+                if let Some(index) = open_tags.iter().rposition(|(open, _)| open == name) {
+                    open_tags.truncate(index);
+                }
+            }
+            Token::Open { .. } => {}
+        }
+    }
+    if graphemes.iter().map(|(grapheme, _)| grapheme.width()).sum::<usize>() <= max_width {
+        return input.to_string();
+    }
+    graphemes.extend(gap.graphemes(true).map(|grapheme| (grapheme, Vec::new())));
+
+    let mut output = String::with_capacity(input.len());
+    let mut current: &[(&str, &str)] = &[];
+    let mut remaining = max_width;
+    for (grapheme, tags) in graphemes.iter().cycle().skip(offset % graphemes.len()).take(graphemes.len()) {
+        let grapheme_width = grapheme.width();
+        if grapheme_width > remaining {
+            break;
+        }
+        let common = current.iter().zip(tags).take_while(|(current, tag)| current == tag).count();
+        for (name, _) in current[common..].iter().rev() {
+            output.push_str(&format!("</{name}>"));
+        }
+        for (_, source) in &tags[common..] {
+            output.push_str(source);
+        }
+        output.push_str(&escape(grapheme));
+        current = tags;
+        remaining -= grapheme_width;
+    }
+    for (name, _) in current.iter().rev() {
+        output.push_str(&format!("</{name}>"));
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_that_looks_like_markup_is_escaped() {
+        assert_eq!(MarkupMode::Escape.apply("a < b & c"), "a &lt; b &amp; c");
+        assert_eq!(MarkupMode::Sanitize.apply("a < b & c"), "a &lt; b &amp; c");
+        assert_eq!(MarkupMode::Strip.apply("a < b & c"), "a &lt; b &amp; c");
+    }
+
+    #[test]
+    fn stray_closing_tag_is_dropped() {
+        assert_eq!(sanitize("text</b> more"), "text more");
+        assert_eq!(sanitize("</span></b>"), "");
+    }
+
+    #[test]
+    fn unclosed_tags_are_closed() {
+        assert_eq!(sanitize("<b>bold"), "<b>bold</b>");
+        assert_eq!(sanitize("<b><i>both</b> after"), "<b><i>both</i></b> after");
+    }
+
+    #[test]
+    fn disallowed_tags_are_removed() {
+        assert_eq!(sanitize("<span color='red'>red</span>"), "red");
+        assert_eq!(sanitize("<img src='x.png' alt='a &amp; b'/>"), "a &amp; b");
+        assert_eq!(sanitize("<a>no target</a>"), "no target");
+    }
+
+    #[test]
+    fn entities_are_decoded() {
+        assert_eq!(plain_text("&lt;b&gt; &amp; &quot;&apos; &#65;&#x42;"), "<b> & \"' AB");
+        assert_eq!(plain_text("&unknown; &#xZZ; & alone"), "&unknown; &#xZZ; & alone");
+        // Decoded text is escaped again, so entities can't smuggle tags in
+        assert_eq!(sanitize("&lt;b&gt;"), "&lt;b&gt;");
+    }
+
+    #[test]
+    fn attributes_are_parsed_with_any_quoting() {
+        assert_eq!(links("<a href=\"https://a.example\">a</a>"), ["https://a.example"]);
+        assert_eq!(links("<a href='https://b.example/?q=\"x\"'>b</a>"), ["https://b.example/?q=\"x\""]);
+        assert_eq!(links("<a href=https://c.example>c</a>"), ["https://c.example"]);
+        assert_eq!(links("<a href = \"https://d.example?a=1&amp;b=2\">d</a>"), ["https://d.example?a=1&b=2"]);
+    }
+
+    #[test]
+    fn attribute_values_are_escaped() {
+        assert_eq!(
+            sanitize("<a href='x\" onclick=\"y'>link</a>"),
+            "<a href=\"x&quot; onclick=&quot;y\">link</a>"
+        );
+    }
+
+    #[test]
+    fn malformed_tags_are_text() {
+        assert_eq!(sanitize("<b"), "&lt;b");
+        assert_eq!(sanitize("<1>"), "&lt;1&gt;");
+        assert_eq!(sanitize("<bhref='x'>"), "&lt;bhref=&apos;x&apos;&gt;");
+        assert_eq!(plain_text("<a href='unterminated>text"), "<a href='unterminated>text");
+    }
+
+    #[test]
+    fn strip_keeps_only_text() {
+        assert_eq!(MarkupMode::Strip.apply("<b>bold</b> <a href='x'>link</a>"), "bold link");
+    }
+
+    #[test]
+    fn width_counts_columns() {
+        assert_eq!(width("abc"), 3);
+        assert_eq!(width("漢字"), 4);
+        assert_eq!(width("👍🏽"), 2);
+        assert_eq!(width("👨‍👩‍👧"), 2);
+        assert_eq!(width("e\u{301}"), 1);
+    }
+
+    #[test]
+    fn short_text_is_not_truncated() {
+        assert_eq!(truncate("<b>abc</b>", 3, "…"), "<b>abc</b>");
+    }
+
+    #[test]
+    fn truncation_includes_the_ellipsis() {
+        assert_eq!(truncate("abcdef", 4, "…"), "abc…");
+        assert_eq!(truncate("abcdef", 5, "..."), "ab...");
+        // An ellipsis wider than the limit is left out
+        assert_eq!(truncate("abcdef", 2, "..."), "ab");
+        assert_eq!(truncate("abcdef", 0, "…"), "");
+    }
+
+    #[test]
+    fn wide_characters_are_not_split() {
+        // 漢 would take the 4th and 5th column, only the ellipsis fits there
+        assert_eq!(truncate("abc漢字", 5, "…"), "abc…");
+        assert_eq!(truncate("abc漢字", 6, "…"), "abc漢…");
+    }
+
+    #[test]
+    fn grapheme_clusters_are_not_split() {
+        assert_eq!(truncate("a👨‍👩‍👧b", 3, "…"), "a…");
+        assert_eq!(truncate("a👨‍👩‍👧bc", 4, "…"), "a👨‍👩‍👧…");
+        assert_eq!(truncate("e\u{301}e\u{301}e\u{301}", 2, "…"), "e\u{301}…");
+    }
+
+    #[test]
+    fn tags_open_at_the_cut_are_closed() {
+        assert_eq!(truncate("<b>bold <i>italic</i></b>", 8, "…"), "<b>bold <i>it…</i></b>");
+        assert_eq!(truncate("<b>bold</b> plain", 6, "…"), "<b>bold</b> …");
+    }
+
+    #[test]
+    fn truncated_text_stays_escaped() {
+        assert_eq!(truncate("a &amp; b &lt; c", 6, "…"), "a &amp; b…");
+        assert_eq!(truncate("abcdef", 4, "<>"), "ab&lt;&gt;");
+    }
+
+    #[test]
+    fn marquee_leaves_short_text_alone() {
+        assert_eq!(marquee("<b>abc</b>", 3, 5, " | "), "<b>abc</b>");
+    }
+
+    #[test]
+    fn marquee_wraps_around_after_the_gap() {
+        assert_eq!(marquee("abcdef", 0, 4, "|"), "abcd");
+        assert_eq!(marquee("abcdef", 4, 4, "|"), "ef|a");
+        // The cycle is the text and the gap, 7 grapheme clusters
+        assert_eq!(marquee("abcdef", 7, 4, "|"), "abcd");
+    }
+
+    #[test]
+    fn marquee_reopens_tags() {
+        assert_eq!(marquee("ab<b>cd</b>ef", 1, 4, "|"), "b<b>cd</b>e");
+        assert_eq!(marquee("ab<b>cd</b>ef", 3, 4, "|"), "<b>d</b>ef|");
+        assert_eq!(marquee("<b>abcdef</b>", 5, 3, "|"), "<b>f</b>|<b>a</b>");
+    }
+
+    #[test]
+    fn marquee_does_not_split_wide_characters_or_clusters() {
+        assert_eq!(marquee("a漢字b", 0, 4, "|"), "a漢");
+        assert_eq!(marquee("a👨‍👩‍👧bcd", 1, 3, "|"), "👨‍👩‍👧b");
+    }
+
+    #[test]
+    fn marquee_keeps_text_escaped() {
+        assert_eq!(marquee("a &lt; b &amp; c", 1, 5, "|"), " &lt; b ");
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+
+use futures_util::StreamExt;
+use zbus::{
+    connection,
+    fdo::MonitoringProxy,
+    message::{Flags, Type},
+    zvariant::Value,
+    Connection, MatchRule, MessageStream, Result,
+};
+
+use crate::{Notification, NotificationServer, NOTIFICATIONS_NAME, OBJECT_PATH};
+
+type NotifyArgs<'a> = (String, u32, String, String, String, Vec<String>, HashMap<String, Value<'a>>, i32);
+
+/// Mirrors notifications handled by another daemon, e.g. mako, into the history of `connection`'s server.
+/// Observes `Notify` calls along with their replies, which carry the ids, and `NotificationClosed` signals
+pub async fn run(connection: Connection) -> Result<()> {
+    // A monitor connection can't be used for anything else
+    let monitor = connection::Builder::session()?.build().await?;
+    let rules = [
+        // Match rules take only unique names as destination, calls to other names are skipped below
+        format!("type='method_call',interface='{NOTIFICATIONS_NAME}',member='Notify'"),
+        format!("type='method_return',sender='{NOTIFICATIONS_NAME}'"),
+        format!("type='error',sender='{NOTIFICATIONS_NAME}'"),
+        format!("type='signal',sender='{NOTIFICATIONS_NAME}',interface='{NOTIFICATIONS_NAME}',member='NotificationClosed'"),
+    ]
+    .iter()
+    .map(|rule| MatchRule::try_from(rule.as_str()).map(MatchRule::into_owned))
+    .collect::<Result<Vec<_>>>()?;
+    MonitoringProxy::new(&monitor).await?.become_monitor(&rules, 0).await?;
+
+    // Notify calls waiting for their reply by the caller and serial number of the call
+    let mut pending: HashMap<(String, NonZeroU32), Notification> = HashMap::new();
+    let mut messages = MessageStream::from(&monitor);
+    while let Some(message) = messages.next().await {
+        let message = message?;
+        let header = message.header();
+        match message.message_type() {
+            Type::MethodCall => {
+                // Nothing will carry the id, so the entry would never leave `pending`. Replies from daemons
+                // owning other names, e.g. a popup daemon Glance forwards to, aren't observed
+                if header.primary().flags().contains(Flags::NoReplyExpected)
+                    || header.destination().is_none_or(|destination| destination.as_str() != NOTIFICATIONS_NAME)
+                {
+                    continue;
+                }
+                let body = message.body();
+                let (Some(sender), Ok(args)) = (header.sender(), body.deserialize::<NotifyArgs>()) else {
+                    continue;
+                };
+                let (app_name, _, _, summary, body, actions, hints, _) = args;
+                let mut notification = Notification::new(&app_name, &summary, &body, actions, &hints, None);
+                // The other daemon talks to the client, Glance never sends it signals about this notification
+                notification.actions.clear();
+                notification.closed = true;
+                pending.insert((sender.to_string(), header.primary().serial_num()), notification);
+            }
+            Type::MethodReturn => {
+                let (Some(destination), Some(serial)) = (header.destination(), header.reply_serial()) else {
+                    continue;
+                };
+                let Some(notification) = pending.remove(&(destination.to_string(), serial)) else {
+                    continue;
+                };
+                if let Ok(id) = message.body().deserialize::<u32>() {
+                    server(&connection).await?.get_mut().await.mirror(id, notification);
+                }
+            }
+            Type::Signal => {
+                if let Ok((id, reason)) = message.body().deserialize::<(u32, u32)>() {
+                    server(&connection).await?.get_mut().await.mirror_closed(id, reason);
+                }
+            }
+            Type::Error => {
+                if let (Some(destination), Some(serial)) = (header.destination(), header.reply_serial()) {
+                    pending.remove(&(destination.to_string(), serial));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn server(connection: &Connection) -> Result<zbus::object_server::InterfaceRef<NotificationServer>> {
+    connection.object_server().interface::<_, NotificationServer>(OBJECT_PATH).await
+}
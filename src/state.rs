@@ -0,0 +1,120 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use zbus::Connection;
+
+use crate::NotificationServer;
+
+/// The part of `NotificationServer` that survives restarts
+#[derive(Serialize, Deserialize)]
+struct State<H> {
+    history: H,
+    visible_on_bar: Option<usize>,
+    last_notification_id: u32,
+}
+
+/// `$XDG_STATE_HOME/glance/state.json`, `$XDG_STATE_HOME` defaults to `~/.local/state`
+fn state_file() -> Option<PathBuf> {
+    let state_home = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))?;
+    Some(state_home.join("glance").join("state.json"))
+}
+
+/// Writes snapshots of the state saved by `NotificationServer` off the runtime thread, so a slow disk
+/// doesn't hold D-Bus messages up. Only the newest one is written, the ones replaced meanwhile are skipped
+pub async fn write_snapshots(mut snapshots: watch::Receiver<Vec<u8>>) {
+    let Some(path) = state_file() else {
+        return;
+    };
+    while snapshots.changed().await.is_ok() {
+        let contents = snapshots.borrow_and_update().clone();
+        let target = path.clone();
+        let result = tokio::task::spawn_blocking(move || write_atomically(&target, &contents))
+            .await
+            .unwrap_or_else(|err| Err(io::Error::other(err)));
+        if let Err(err) = result {
+            eprintln!("Failed to save notification history to {}: {err}", path.display());
+        }
+    }
+}
+
+/// Writes to a temporary file and renames it over the target,
+/// so the file holds either the previous or the new state even if the process is killed
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    let temporary = path.with_extension("json.tmp");
+    let mut file = File::create(&temporary)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)?;
+    File::open(dir)?.sync_all()
+}
+
+impl NotificationServer {
+    /// Writes history to disk if persistence is enabled and Glance is the active daemon
+    pub(crate) fn save(&self) {
+        if !self.config.persist || !self.active {
+            return;
+        }
+        let state = State {
+            history: &self.history,
+            visible_on_bar: self.visible_on_bar,
+            last_notification_id: self.last_notification_id,
+        };
+        match serde_json::to_vec(&state) {
+            Ok(contents) => {
+                self.snapshots.send_replace(contents);
+            }
+            Err(err) => eprintln!("Failed to serialize notification history: {err}"),
+        }
+    }
+
+    /// Loads history saved by a previous run. Starts from scratch if there's none or it can't be read
+    pub(crate) fn restore(&mut self) {
+        let Some(path) = state_file() else {
+            return;
+        };
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return,
+            Err(err) => {
+                eprintln!("Failed to read notification history from {}: {err}", path.display());
+                return;
+            }
+        };
+        match serde_json::from_slice::<State<_>>(&contents) {
+            Ok(state) => {
+                self.history = state.history;
+                // Their senders can't be told about them anymore, so actions and close signals are off
+                for notification in self.history.values_mut() {
+                    notification.closed = true;
+                }
+                self.visible_on_bar = state.visible_on_bar.filter(|&index| index < self.history.len());
+                self.last_notification_id = state.last_notification_id;
+            }
+            Err(err) => eprintln!("Failed to parse notification history from {}: {err}", path.display()),
+        }
+    }
+
+    /// Re-arms expiration timers of restored notifications. The ones that passed while the daemon
+    /// wasn't running expire right away
+    pub(crate) fn restore_expirations(&mut self, connection: &Connection) {
+        let now = SystemTime::now();
+        let pending: Vec<_> = self
+            .history
+            .iter()
+            .filter_map(|(id, notification)| Some((*id, notification.expires_at?)))
+            .collect();
+        for (id, expires_at) in pending {
+            let timeout = expires_at.duration_since(now).unwrap_or_default();
+            self.schedule_expiration(connection.clone(), id, timeout);
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::markup;
+
+/// Placeholders that can be used in formats
+pub const PLACEHOLDERS: &[&str] = &["app", "summary", "body", "urgency", "time", "date", "age", "unread", "total", "position"];
+
+/// Provides values of the placeholders while rendering
+pub trait Values {
+    /// Pango markup of the placeholder. With `literal` set, the content has to be escaped
+    /// regardless of how markup in the field is treated otherwise
+    fn markup(&self, name: &str, literal: bool) -> String;
+
+    /// Appended to truncated values
+    fn ellipsis(&self) -> &str;
+}
+
+/// A format such as `[{app}] <b>{summary}</b>{?body}: {body|oneline}{/}`.
+///
+/// - `{name}` is replaced with the value of a placeholder
+/// - `{name|filter|filter:argument}` passes the value through filters
+/// - `{?name}...{/}` is rendered only if the value isn't empty, `{!name}...{/}` only if it is
+/// - `{{` is a literal brace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Template {
+    source: String,
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Placeholder { name: String, filters: Vec<Filter> },
+    Section { name: String, negated: bool, nodes: Vec<Node> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    /// Keeps at most that many display columns
+    Truncate(usize),
+    Upper,
+    Lower,
+    /// Shows the content literally, even if the field allows markup
+    Escape,
+    /// Joins lines with spaces
+    Oneline,
+}
+
+impl Filter {
+    fn parse(filter: &str) -> Result<Self, String> {
+        let (name, argument) = match filter.split_once(':') {
+            Some((name, argument)) => (name.trim(), Some(argument.trim())),
+            None => (filter.trim(), None),
+        };
+        match (name, argument) {
+            ("truncate", Some(length)) => length
+                .parse()
+                .map(Filter::Truncate)
+                .map_err(|_| format!("truncate expects a number of columns, got \"{length}\"")),
+            ("truncate", None) => Err("truncate expects a number of columns, e.g. truncate:80".to_string()),
+            ("upper", None) => Ok(Filter::Upper),
+            ("lower", None) => Ok(Filter::Lower),
+            ("escape", None) => Ok(Filter::Escape),
+            ("oneline", None) => Ok(Filter::Oneline),
+            ("upper" | "lower" | "escape" | "oneline", Some(_)) => Err(format!("filter {name} takes no argument")),
+            _ => Err(format!(
+                "unknown filter \"{name}\", expected one of: truncate, upper, lower, escape, oneline"
+            )),
+        }
+    }
+
+    fn apply(&self, value: String, ellipsis: &str) -> String {
+        match self {
+            Filter::Truncate(width) => markup::truncate(&value, *width, ellipsis),
+            Filter::Upper => markup::map_text(&value, |text| text.to_uppercase()),
+            Filter::Lower => markup::map_text(&value, |text| text.to_lowercase()),
+            Filter::Oneline => markup::map_text(&value, |text| text.replace("\r\n", " ").replace(['\r', '\n'], " ")),
+            // Handled when the value is produced
+            Filter::Escape => value,
+        }
+    }
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parser = Parser { rest: source };
+        let nodes = parser.nodes(None)?;
+        Ok(Self { source: source.to_string(), nodes })
+    }
+
+    pub fn render(&self, values: &impl Values) -> String {
+        let mut output = String::new();
+        render_nodes(&self.nodes, values, &mut output);
+        output
+    }
+
+    /// Whether the placeholder appears anywhere in the template
+    pub fn uses(&self, placeholder: &str) -> bool {
+        fn uses(nodes: &[Node], placeholder: &str) -> bool {
+            nodes.iter().any(|node| match node {
+                Node::Text(_) => false,
+                Node::Placeholder { name, .. } => name == placeholder,
+                Node::Section { name, nodes, .. } => name == placeholder || uses(nodes, placeholder),
+            })
+        }
+        uses(&self.nodes, placeholder)
+    }
+}
+
+fn render_nodes(nodes: &[Node], values: &impl Values, output: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Placeholder { name, filters } => {
+                let literal = filters.contains(&Filter::Escape);
+                let value = filters
+                    .iter()
+                    .fold(values.markup(name, literal), |value, filter| filter.apply(value, values.ellipsis()));
+                output.push_str(&value);
+            }
+            Node::Section { name, negated, nodes } => {
+                let empty = markup::plain_text(&values.markup(name, false)).trim().is_empty();
+                if empty == *negated {
+                    render_nodes(nodes, values, output);
+                }
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    /// Parses until the end of input or, inside a section, until its `{/}`
+    fn nodes(&mut self, section: Option<&str>) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+        loop {
+            let Some(start) = self.rest.find('{') else {
+                text.push_str(self.rest);
+                self.rest = "";
+                break;
+            };
+            text.push_str(&self.rest[..start]);
+            self.rest = &self.rest[start..];
+
+            if let Some(rest) = self.rest.strip_prefix("{{") {
+                text.push('{');
+                self.rest = rest;
+                continue;
+            }
+            let end = self
+                .rest
+                .find('}')
+                .ok_or_else(|| format!("unclosed placeholder \"{}\"", self.rest))?;
+            let tag = &self.rest[1..end];
+            self.rest = &self.rest[end + 1..];
+
+            if !text.is_empty() {
+                nodes.push(Node::Text(std::mem::take(&mut text)));
+            }
+            if tag == "/" {
+                return match section {
+                    Some(_) => Ok(nodes),
+                    None => Err("{/} without an opening {?placeholder}".to_string()),
+                };
+            }
+            if let Some(name) = tag.strip_prefix('?').or_else(|| tag.strip_prefix('!')) {
+                let name = placeholder(name)?;
+                let negated = tag.starts_with('!');
+                let nodes_inside = self.nodes(Some(name))?;
+                nodes.push(Node::Section { name: name.to_string(), negated, nodes: nodes_inside });
+                continue;
+            }
+
+            let mut parts = tag.split('|');
+            let name = placeholder(parts.next().unwrap_or_default())?.to_string();
+            let filters = parts.map(Filter::parse).collect::<Result<_, _>>()?;
+            nodes.push(Node::Placeholder { name, filters });
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        match section {
+            Some(name) => Err(format!("section {{?{name}}} is missing its closing {{/}}")),
+            None => Ok(nodes),
+        }
+    }
+}
+
+fn placeholder(name: &str) -> Result<&str, String> {
+    let name = name.trim();
+    if PLACEHOLDERS.contains(&name) {
+        Ok(name)
+    } else {
+        Err(format!(
+            "unknown placeholder {{{name}}}, expected one of: {}",
+            PLACEHOLDERS.join(", ")
+        ))
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Template::parse(&source)
+    }
+}
+
+impl From<Template> for String {
+    fn from(template: Template) -> Self {
+        template.source
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fields(&'static [(&'static str, &'static str)]);
+
+    impl Values for Fields {
+        fn markup(&self, name: &str, literal: bool) -> String {
+            let value = self.0.iter().find(|(field, _)| *field == name).map_or("", |(_, value)| value);
+            if literal { markup::escape(value).into_owned() } else { value.to_string() }
+        }
+
+        fn ellipsis(&self) -> &str {
+            "…"
+        }
+    }
+
+    fn render(template: &str, fields: &'static [(&'static str, &'static str)]) -> String {
+        Template::parse(template).unwrap().render(&Fields(fields))
+    }
+
+    #[test]
+    fn placeholders_are_replaced() {
+        assert_eq!(render("[{app}] {summary}", &[("app", "mail"), ("summary", "Hi")]), "[mail] Hi");
+        assert_eq!(render("{ app }", &[("app", "mail")]), "mail");
+    }
+
+    #[test]
+    fn values_are_not_parsed_as_templates() {
+        assert_eq!(render("{summary}", &[("summary", "{unread} {{")]), "{unread} {{");
+    }
+
+    #[test]
+    fn sections_depend_on_the_value() {
+        let template = "{summary}{?body}: {body}{/}{!body} (empty){/}";
+        assert_eq!(render(template, &[("summary", "Hi"), ("body", "there")]), "Hi: there");
+        assert_eq!(render(template, &[("summary", "Hi"), ("body", "")]), "Hi (empty)");
+        // Markup without text counts as empty
+        assert_eq!(render(template, &[("summary", "Hi"), ("body", "<b> </b>")]), "Hi (empty)");
+    }
+
+    #[test]
+    fn sections_nest() {
+        let template = "{?app}[{app}{?body} {body}{/}]{/}";
+        assert_eq!(render(template, &[("app", "a"), ("body", "b")]), "[a b]");
+        assert_eq!(render(template, &[("app", "a")]), "[a]");
+        assert_eq!(render(template, &[("body", "b")]), "");
+    }
+
+    #[test]
+    fn double_brace_is_literal() {
+        assert_eq!(render("{{app} {app}", &[("app", "mail")]), "{app} mail");
+        assert_eq!(render("a }} b", &[]), "a }} b");
+    }
+
+    #[test]
+    fn filters_are_applied_in_order() {
+        assert_eq!(render("{app|upper}", &[("app", "mail")]), "MAIL");
+        assert_eq!(render("{app|lower}", &[("app", "MAIL")]), "mail");
+        assert_eq!(render("{body|oneline}", &[("body", "a\nb\r\nc")]), "a b c");
+        assert_eq!(render("{body|truncate:4}", &[("body", "abcdef")]), "abc…");
+        assert_eq!(render("{body|oneline|truncate:4|upper}", &[("body", "abc\ndef")]), "ABC…");
+    }
+
+    #[test]
+    fn filters_keep_tags() {
+        assert_eq!(render("{body|upper}", &[("body", "<b>bold</b> &amp;")]), "<b>BOLD</b> &amp;");
+    }
+
+    #[test]
+    fn escape_filter_shows_markup_literally() {
+        assert_eq!(render("{summary|escape}", &[("summary", "<b>x</b>")]), "&lt;b&gt;x&lt;/b&gt;");
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        let err = Template::parse("{app} {sender}").unwrap_err();
+        assert!(err.contains("unknown placeholder {sender}"), "{err}");
+        assert!(Template::parse("{?sender}x{/}").is_err());
+        assert!(Template::parse("{}").is_err());
+    }
+
+    #[test]
+    fn unknown_filters_are_rejected() {
+        let err = Template::parse("{body|reverse}").unwrap_err();
+        assert!(err.contains("unknown filter \"reverse\""), "{err}");
+        assert!(Template::parse("{body|truncate}").is_err());
+        assert!(Template::parse("{body|truncate:many}").is_err());
+        assert!(Template::parse("{body|upper:2}").is_err());
+    }
+
+    #[test]
+    fn unbalanced_sections_are_rejected() {
+        let err = Template::parse("{?body}: {body}").unwrap_err();
+        assert!(err.contains("missing its closing {/}"), "{err}");
+        assert!(Template::parse("{body}{/}").is_err());
+        assert!(Template::parse("{?app}{?body}{/}").is_err());
+    }
+
+    #[test]
+    fn unclosed_placeholders_are_rejected() {
+        assert!(Template::parse("{app").is_err());
+    }
+
+    #[test]
+    fn uses_finds_placeholders_in_sections() {
+        let template = Template::parse("{app}{?body}{age}{/}").unwrap();
+        assert!(template.uses("age"));
+        assert!(template.uses("body"));
+        assert!(!template.uses("time"));
+    }
+
+    #[test]
+    fn source_is_kept() {
+        let source = "[{app}] {{ {?body}{body|truncate:5}{/}";
+        assert_eq!(Template::parse(source).unwrap().to_string(), source);
+    }
+}